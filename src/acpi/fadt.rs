@@ -0,0 +1,35 @@
+/*
+ *  Fixed ACPI Description Table.
+ *
+ *  Only the fields the kernel uses are parsed.
+ */
+
+use acpi;
+
+// offset of the century field from the start of the table
+const CENTURY_OFFSET: usize = 108;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// CMOS register holding the century of the RTC date, if there is one.
+    pub century_register: Option<u8>
+}
+
+impl Fadt {
+    /// Finds and parses the FADT, returns `None` if there is none.
+    pub fn parse() -> Option<Fadt> {
+        let table = acpi::find_table(b"FACP")?;
+
+        // ACPI 1.0 tables are shorter and have no century field
+        let century_register = if table.length() > CENTURY_OFFSET {
+            match unsafe { *((table.start_address() + CENTURY_OFFSET) as *const u8) } {
+                0 => None,
+                register => Some(register)
+            }
+        } else {
+            None
+        };
+
+        Some(Fadt { century_register: century_register })
+    }
+}
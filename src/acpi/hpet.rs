@@ -0,0 +1,43 @@
+/*
+ *  HPET description table.
+ */
+
+use core::ptr;
+
+use acpi;
+use memory::PhysicalAddress;
+
+// address space id of a Generic Address Structure in system memory
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HpetTable {
+    /// Physical address of the register block.
+    pub address: PhysicalAddress,
+    pub hpet_number: u8,
+    /// Smallest period in counter ticks which can be used in periodic mode
+    /// without losing interrupts.
+    pub minimum_tick: u16
+}
+
+impl HpetTable {
+    /// Finds and parses the HPET table, returns `None` if there is none or
+    /// its registers aren't memory mapped.
+    pub fn parse() -> Option<HpetTable> {
+        let table = acpi::find_table(b"HPET")?;
+        let data = table.data_address();
+
+        // event timer block id, then the base address as a Generic Address Structure
+        unsafe {
+            if ptr::read_unaligned((data + 4) as *const u8) != ADDRESS_SPACE_MEMORY {
+                return None;
+            }
+
+            Some(HpetTable {
+                address: ptr::read_unaligned((data + 8) as *const u64) as PhysicalAddress,
+                hpet_number: ptr::read_unaligned((data + 16) as *const u8),
+                minimum_tick: ptr::read_unaligned((data + 17) as *const u16)
+            })
+        }
+    }
+}
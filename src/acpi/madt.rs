@@ -0,0 +1,125 @@
+/*
+ *  Multiple APIC Description Table.
+ *
+ *  Lists the local APICs of all processors, the I/O APICs and how ISA IRQs
+ *  are wired to the I/O APIC inputs when that differs from the identity.
+ */
+
+use core::ptr;
+
+use alloc::vec::Vec;
+
+use acpi;
+use memory::PhysicalAddress;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+// set in the MADT flags if the machine also has 8259 PICs
+const FLAG_PCAT_COMPAT: u32 = 1 << 0;
+// set in the local APIC entry flags if the processor can be used
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysicalAddress,
+    /// First global system interrupt handled by this I/O APIC.
+    pub gsi_base: u32
+}
+
+/// Remaps an ISA IRQ to a different global system interrupt, optionally with
+/// a different polarity and trigger mode than ISA's active high and edge.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16
+}
+
+impl InterruptOverride {
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    pub fn level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+pub struct Madt {
+    pub local_apic_address: PhysicalAddress,
+    pub has_legacy_pics: bool,
+    /// APIC ids of all usable processors.
+    pub local_apic_ids: Vec<u8>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptOverride>
+}
+
+impl Madt {
+    /// Finds and parses the MADT, returns `None` if there is none.
+    pub fn parse() -> Option<Madt> {
+        let table = acpi::find_table(b"APIC")?;
+        let data = table.data_address();
+        let end = table.start_address() + table.length();
+
+        let mut madt = unsafe {
+            Madt {
+                local_apic_address: read::<u32>(data) as PhysicalAddress,
+                has_legacy_pics: read::<u32>(data + 4) & FLAG_PCAT_COMPAT != 0,
+                local_apic_ids: Vec::new(),
+                io_apics: Vec::new(),
+                overrides: Vec::new()
+            }
+        };
+
+        // variable length entries, each starting with its type and length
+        let mut entry = data + 8;
+        while entry + 2 <= end {
+            let (entry_type, length) = unsafe { (read::<u8>(entry), read::<u8>(entry + 1) as usize) };
+            if length < 2 || entry + length > end {
+                break;
+            }
+
+            unsafe {
+                match entry_type {
+                    ENTRY_LOCAL_APIC => {
+                        if read::<u32>(entry + 4) & LOCAL_APIC_ENABLED != 0 {
+                            madt.local_apic_ids.push(read::<u8>(entry + 3));
+                        }
+                    }
+                    ENTRY_IO_APIC => madt.io_apics.push(IoApicEntry {
+                        id: read::<u8>(entry + 2),
+                        address: read::<u32>(entry + 4) as PhysicalAddress,
+                        gsi_base: read::<u32>(entry + 8)
+                    }),
+                    ENTRY_INTERRUPT_OVERRIDE => madt.overrides.push(InterruptOverride {
+                        source: read::<u8>(entry + 3),
+                        gsi: read::<u32>(entry + 4),
+                        flags: read::<u16>(entry + 8)
+                    }),
+                    ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                        madt.local_apic_address = read::<u64>(entry + 4) as PhysicalAddress;
+                    }
+                    _ => {}
+                }
+            }
+
+            entry += length;
+        }
+
+        Some(madt)
+    }
+
+    /// Returns the override for an ISA IRQ, if there is one.
+    pub fn interrupt_override(&self, irq: u8) -> Option<&InterruptOverride> {
+        self.overrides.iter().find(|o| o.source == irq)
+    }
+}
+
+// MADT entries aren't aligned
+unsafe fn read<T>(addr: PhysicalAddress) -> T {
+    ptr::read_unaligned(addr as *const T)
+}
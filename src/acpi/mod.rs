@@ -0,0 +1,228 @@
+/*
+ *  ACPI table discovery.
+ *
+ *  The RSDP is taken from the multiboot2 ACPI tags if the bootloader passed
+ *  one, otherwise the BIOS areas are scanned for its signature. That happens
+ *  before the frame allocator exists, since part of those areas is usable
+ *  memory which is only guaranteed to still hold the firmware's data (and to
+ *  be identity mapped) during early boot. Tables are identity mapped on
+ *  demand, since firmware keeps them in reserved memory which the kernel
+ *  doesn't map otherwise.
+ */
+
+use core::{mem, ptr, slice, str};
+
+use multiboot2::BootInformation;
+use spin::Once;
+
+use mb_tags::{self, TAG_ACPI_OLD_RSDP, TAG_ACPI_NEW_RSDP};
+use memory::{self, PhysicalAddress, NO_EXECUTE};
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+
+// areas the RSDP can be in when the bootloader doesn't tell us, the EBDA is
+// usually in the last KiB below 640 KiB
+const BIOS_SEARCH_AREAS: [(usize, usize); 2] = [(0x8_0000, 0xA_0000), (0xE_0000, 0x10_0000)];
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32
+}
+
+// ACPI 2.0 extension of the RSDP, only valid if revision >= 2
+#[repr(C, packed)]
+struct Rsdp2 {
+    v1: Rsdp,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3]
+}
+
+/// Header shared by all System Description Tables.
+#[repr(C, packed)]
+pub struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Length of the whole table, including the header.
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+
+    pub fn start_address(&self) -> PhysicalAddress {
+        self as *const _ as PhysicalAddress
+    }
+
+    /// Address of the table contents following the header.
+    pub fn data_address(&self) -> PhysicalAddress {
+        self.start_address() + mem::size_of::<SdtHeader>()
+    }
+
+    pub fn data_length(&self) -> usize {
+        self.length() - mem::size_of::<SdtHeader>()
+    }
+}
+
+struct RootTable {
+    header: &'static SdtHeader,
+    // RSDT entries are 32 bit, XSDT entries 64 bit
+    entry_size: usize
+}
+
+impl RootTable {
+    fn entries(&self) -> usize {
+        self.header.data_length() / self.entry_size
+    }
+
+    fn entry(&self, index: usize) -> PhysicalAddress {
+        let addr = self.header.data_address() + index * self.entry_size;
+        unsafe {
+            if self.entry_size == 8 {
+                ptr::read_unaligned(addr as *const u64) as PhysicalAddress
+            } else {
+                ptr::read_unaligned(addr as *const u32) as PhysicalAddress
+            }
+        }
+    }
+}
+
+// address and entry size of the root table the RSDP points to
+static ROOT_POINTER: Once<(PhysicalAddress, usize)> = Once::new();
+static ROOT_TABLE: Once<RootTable> = Once::new();
+
+/// Locates the RSDP and remembers the root table it points to. Has to be
+/// called before the frame allocator is created.
+pub fn init_early(boot_info: &BootInformation) {
+    let rsdp = match find_rsdp(boot_info) {
+        Some(rsdp) => rsdp,
+        None => {
+            println!("acpi: no RSDP found");
+            return;
+        }
+    };
+
+    ROOT_POINTER.call_once(|| unsafe {
+        let rsdp = &*(rsdp as *const Rsdp2);
+        if rsdp.v1.revision >= 2 && rsdp.xsdt_address != 0 {
+            (rsdp.xsdt_address as PhysicalAddress, 8)
+        } else {
+            (rsdp.v1.rsdt_address as PhysicalAddress, 4)
+        }
+    });
+}
+
+/// Maps the root table found by `init_early`. Returns false if the machine
+/// has no (valid) ACPI tables. Memory has to be initialized first.
+pub fn init() -> bool {
+    let (root_addr, entry_size) = match ROOT_POINTER.try() {
+        Some(&root_pointer) => root_pointer,
+        None => return false
+    };
+
+    let header = match map_table(root_addr) {
+        Some(header) => header,
+        None => {
+            println!("acpi: invalid root table at 0x{:x}", root_addr);
+            return false;
+        }
+    };
+
+    let root = ROOT_TABLE.call_once(|| RootTable { header: header, entry_size: entry_size });
+    println!("acpi: {} at 0x{:x} with {} tables", root.header.signature(), root_addr, root.entries());
+    true
+}
+
+/// Returns the first table with the given signature, eg. `b"APIC"` for the MADT.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    let root = ROOT_TABLE.try()?;
+    (0..root.entries())
+        .filter_map(|index| map_table(root.entry(index)))
+        .find(|table| &table.signature == signature)
+}
+
+// maps a table and checks its checksum
+fn map_table(addr: PhysicalAddress) -> Option<&'static SdtHeader> {
+    if addr == 0 {
+        return None;
+    }
+
+    // the header has to be mapped before we know how long the table is
+    memory::identity_map(addr, mem::size_of::<SdtHeader>(), NO_EXECUTE);
+    let header = unsafe { &*(addr as *const SdtHeader) };
+    if header.length() < mem::size_of::<SdtHeader>() {
+        return None;
+    }
+
+    memory::identity_map(addr, header.length(), NO_EXECUTE);
+    if checksum(addr, header.length()) {
+        Some(header)
+    } else {
+        None
+    }
+}
+
+fn find_rsdp(boot_info: &BootInformation) -> Option<PhysicalAddress> {
+    // the bootloader copies the RSDP into the tag, so it's mapped already
+    for &tag_type in [TAG_ACPI_NEW_RSDP, TAG_ACPI_OLD_RSDP].iter() {
+        if let Some(tag) = mb_tags::find_tag(boot_info, tag_type) {
+            if is_valid_rsdp(tag.data_address()) {
+                return Some(tag.data_address());
+            }
+        }
+    }
+
+    // both areas are still covered by the boot identity mapping
+    for &(start, end) in BIOS_SEARCH_AREAS.iter() {
+        // the RSDP is always 16 byte aligned
+        if let Some(addr) = (start..end).step_by(16).find(|&addr| is_valid_rsdp(addr)) {
+            return Some(addr);
+        }
+    }
+
+    None
+}
+
+fn is_valid_rsdp(addr: PhysicalAddress) -> bool {
+    let rsdp = unsafe { &*(addr as *const Rsdp) };
+    if &rsdp.signature != RSDP_SIGNATURE || !checksum(addr, mem::size_of::<Rsdp>()) {
+        return false;
+    }
+
+    if rsdp.revision >= 2 {
+        let length = unsafe { (*(addr as *const Rsdp2)).length as usize };
+        return checksum(addr, length);
+    }
+    true
+}
+
+// all bytes of an ACPI structure, including its checksum field, sum up to zero
+fn checksum(addr: PhysicalAddress, length: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(addr as *const u8, length) };
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
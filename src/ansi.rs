@@ -0,0 +1,345 @@
+/*
+ *  The common subset of ANSI/VT100 escape sequences.
+ *
+ *  The parser turns a character stream into printable characters and the
+ *  control sequences embedded in it. Unsupported sequences are parsed
+ *  completely and then dropped, so they never show up as garbage on screen.
+ *  `Terminal` acts on them for every display, which only has to provide the
+ *  cursor and draw the cells.
+ */
+
+use core::cmp::min;
+
+use vga_buffer::{Color, DEFAULT_FOREGROUND, DEFAULT_BACKGROUND};
+
+const ESC: char = '\x1B';
+// cancel a sequence in progress
+const CAN: char = '\x18';
+const SUB: char = '\x1A';
+
+pub const MAX_PARAMS: usize = 8;
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    /// A character to display, including control characters like `\n`.
+    Print(char),
+    SaveCursor,
+    RestoreCursor,
+    Csi(CsiSequence)
+}
+
+/// A Control Sequence Introducer sequence, `ESC [ params final_byte`.
+#[derive(Debug, Clone, Copy)]
+pub struct CsiSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Set for DEC private sequences, which start with `?`.
+    pub private: bool,
+    pub final_byte: u8
+}
+
+impl CsiSequence {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Parameter at `index`, or `default` if it was left out.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        if index < self.len { self.params[index] } else { default }
+    }
+
+    /// Parameter at `index` used as a count, where both 0 and leaving it
+    /// out mean 1.
+    pub fn count(&self, index: usize) -> usize {
+        match self.param(index, 1) {
+            0 => 1,
+            count => count as usize
+        }
+    }
+
+    fn push(&mut self, param: u16) {
+        // extra parameters are dropped, no supported sequence needs them
+        if self.len < MAX_PARAMS {
+            self.params[self.len] = param;
+            self.len += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi
+}
+
+pub struct Parser {
+    state: State,
+    sequence: CsiSequence,
+    // digits of the parameter being parsed, None before its first digit
+    current: Option<u16>
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            sequence: CsiSequence { params: [0; MAX_PARAMS], len: 0, private: false, final_byte: 0 },
+            current: None
+        }
+    }
+
+    /// Feeds the next character, returns an action once one is complete.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        if c == CAN || c == SUB {
+            self.state = State::Ground;
+            return None;
+        }
+
+        match self.state {
+            State::Ground => {
+                if c == ESC {
+                    self.state = State::Escape;
+                    None
+                } else {
+                    Some(Action::Print(c))
+                }
+            }
+            State::Escape => {
+                self.state = State::Ground;
+                match c {
+                    '[' => {
+                        self.state = State::Csi;
+                        self.sequence.len = 0;
+                        self.sequence.private = false;
+                        self.current = None;
+                        None
+                    }
+                    '7' => Some(Action::SaveCursor),
+                    '8' => Some(Action::RestoreCursor),
+                    ESC => {
+                        self.state = State::Escape;
+                        None
+                    }
+                    _ => None
+                }
+            }
+            State::Csi => match c {
+                '0'...'9' => {
+                    let digit = (c as u8 - b'0') as u16;
+                    let value = self.current.unwrap_or(0);
+                    self.current = Some(value.saturating_mul(10).saturating_add(digit));
+                    None
+                }
+                ';' => {
+                    let param = self.current.take().unwrap_or(0);
+                    self.sequence.push(param);
+                    None
+                }
+                '?' => {
+                    self.sequence.private = true;
+                    None
+                }
+                // final byte
+                '\x40'...'\x7E' => {
+                    self.state = State::Ground;
+                    if self.current.is_some() || self.sequence.len > 0 {
+                        let param = self.current.take().unwrap_or(0);
+                        self.sequence.push(param);
+                    }
+                    self.sequence.final_byte = c as u8;
+                    Some(Action::Csi(self.sequence))
+                }
+                // intermediate bytes, none of the supported sequences use them
+                '\x20'...'\x3F' => None,
+                _ => {
+                    self.state = State::Ground;
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Colors and rendition selected with SGR sequences.
+#[derive(Debug, Clone, Copy)]
+pub struct Attributes {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool
+}
+
+impl Attributes {
+    pub const fn new(foreground: Color, background: Color) -> Attributes {
+        Attributes { foreground: foreground, background: background, bold: false }
+    }
+
+    /// The color text is drawn in, bold text is shown in the bright variant
+    /// of its color.
+    pub fn text_color(&self) -> Color {
+        if self.bold { self.foreground.brighten() } else { self.foreground }
+    }
+}
+
+/// A screen of character cells driven by a character stream with escape
+/// sequences in it.
+///
+/// The provided methods interpret the stream, implementors keep the cursor
+/// and attributes and put characters into cells.
+pub trait Terminal {
+    /// Size of the screen in characters, as `(rows, columns)`.
+    fn dimensions(&self) -> (usize, usize);
+
+    /// The cursor as `(row, column)`. The column is one past the last one
+    /// after the last cell of a row was written.
+    fn cursor(&self) -> (usize, usize);
+
+    /// Moves the cursor, which isn't redrawn until the write is done.
+    fn move_cursor(&mut self, row: usize, column: usize);
+
+    /// The cursor position saved with `ESC 7` or `CSI s`.
+    fn saved_cursor(&mut self) -> &mut (usize, usize);
+
+    fn parser(&mut self) -> &mut Parser;
+
+    fn attributes(&mut self) -> &mut Attributes;
+
+    /// Called after an SGR sequence changed the attributes.
+    fn attributes_changed(&mut self) {}
+
+    /// Puts a character into the cell at the cursor and advances it,
+    /// wrapping to the next line first if the row is full.
+    fn write_character(&mut self, c: char);
+
+    /// Moves the cursor to the start of the next line, scrolling the screen
+    /// if it's in the last row.
+    fn write_new_line(&mut self);
+
+    /// Blanks the columns `[start, end)` of a row.
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize);
+
+    /// Feeds a character through the escape sequence parser and acts on it.
+    fn process_char(&mut self, c: char) {
+        let action = self.parser().advance(c);
+        match action {
+            Some(Action::Print(c)) => self.put_char(c),
+            Some(Action::SaveCursor) => {
+                let cursor = self.cursor();
+                *self.saved_cursor() = cursor;
+            }
+            Some(Action::RestoreCursor) => {
+                let (row, col) = *self.saved_cursor();
+                self.move_cursor(row, col);
+            }
+            Some(Action::Csi(sequence)) => self.execute_csi(&sequence),
+            None => {}
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        let (row, col) = self.cursor();
+        match c {
+            '\n' => self.write_new_line(),
+            '\r' => self.move_cursor(row, 0),
+            '\t' => {
+                for _ in 0..TAB_WIDTH - col % TAB_WIDTH {
+                    self.write_character(' ');
+                }
+            }
+            // backspace erases the character left of the cursor, but never
+            // goes back past the start of the line
+            '\x08' => {
+                if col > 0 {
+                    self.move_cursor(row, col - 1);
+                    self.clear_columns(row, col - 1, col);
+                }
+            }
+            _ => self.write_character(c)
+        }
+    }
+
+    fn execute_csi(&mut self, sequence: &CsiSequence) {
+        if sequence.private {
+            // eg. showing or hiding the cursor, which we don't support
+            return;
+        }
+
+        let (rows, columns) = self.dimensions();
+        let (row, col) = self.cursor();
+        let col = min(col, columns - 1);
+        match sequence.final_byte {
+            b'A' => self.move_cursor(row.saturating_sub(sequence.count(0)), col),
+            b'B' => self.move_cursor(min(row + sequence.count(0), rows - 1), col),
+            b'C' => self.move_cursor(row, min(col + sequence.count(0), columns - 1)),
+            b'D' => self.move_cursor(row, col.saturating_sub(sequence.count(0))),
+            b'G' => self.move_cursor(row, min(sequence.count(0) - 1, columns - 1)),
+            // positions are 1-based
+            b'H' | b'f' => self.move_cursor(min(sequence.count(0) - 1, rows - 1),
+                                            min(sequence.count(1) - 1, columns - 1)),
+            b'J' => match sequence.param(0, 0) {
+                0 => {
+                    self.clear_columns(row, col, columns);
+                    for row in row + 1..rows {
+                        self.clear_columns(row, 0, columns);
+                    }
+                }
+                1 => {
+                    for row in 0..row {
+                        self.clear_columns(row, 0, columns);
+                    }
+                    self.clear_columns(row, 0, col + 1);
+                }
+                _ => {
+                    for row in 0..rows {
+                        self.clear_columns(row, 0, columns);
+                    }
+                }
+            },
+            b'K' => match sequence.param(0, 0) {
+                0 => self.clear_columns(row, col, columns),
+                1 => self.clear_columns(row, 0, col + 1),
+                _ => self.clear_columns(row, 0, columns)
+            },
+            b'm' => self.select_graphic_rendition(sequence),
+            b's' => {
+                let cursor = self.cursor();
+                *self.saved_cursor() = cursor;
+            }
+            b'u' => {
+                let (row, col) = *self.saved_cursor();
+                self.move_cursor(row, col);
+            }
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, sequence: &CsiSequence) {
+        {
+            let attributes = self.attributes();
+
+            // no parameters at all means reset
+            if sequence.params().is_empty() {
+                *attributes = Attributes::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+            }
+
+            for &param in sequence.params() {
+                match param {
+                    0 => *attributes = Attributes::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+                    1 => attributes.bold = true,
+                    22 => attributes.bold = false,
+                    30...37 => attributes.foreground = Color::from_ansi(param - 30, false),
+                    39 => attributes.foreground = DEFAULT_FOREGROUND,
+                    40...47 => attributes.background = Color::from_ansi(param - 40, false),
+                    49 => attributes.background = DEFAULT_BACKGROUND,
+                    90...97 => attributes.foreground = Color::from_ansi(param - 90, true),
+                    100...107 => attributes.background = Color::from_ansi(param - 100, true),
+                    _ => {}
+                }
+            }
+        }
+
+        self.attributes_changed();
+    }
+}
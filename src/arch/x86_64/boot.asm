@@ -14,6 +14,7 @@ SEG_PRESENT      equ (1 << 47)
 SEG_CODE_64_BIT  equ (1 << 53)
 
 global start
+global stack_guard_page
 extern lm_start
 
 section .text
@@ -208,6 +209,8 @@ p3_table:
     resb 4096
 p2_table:
     resb 4096
+stack_guard_page:
+    resb 4096 ; unmapped once the kernel is remapped, so stack overflows fault
 stack_bottom:
     resb 4096 * 2
 stack_top:
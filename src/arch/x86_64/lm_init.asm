@@ -21,4 +21,8 @@ lm_start:
     mov [VGA_BUFFER_ADDR + 0x08], rax
     mov rax, 0x4F214F644F654F6E
     mov [VGA_BUFFER_ADDR + 0x10], rax
+
+.halt:
+    ; interrupts may be enabled by now, so go back to sleep after each one
     hlt
+    jmp .halt
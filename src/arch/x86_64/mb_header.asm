@@ -19,7 +19,21 @@ header_start:
 
     ; insert optional multiboot tags here
 
+    ; ask for a linear framebuffer, 32 bits per pixel if possible. it's
+    ; optional, without one the kernel keeps using VGA text mode. the grub.cfg
+    ; keeps GRUB in text mode unless the framebuffer console is asked for
+    align 8
+framebuffer_tag_start:
+    dw 5                                            ; type: framebuffer
+    dw 1                                            ; flags: optional
+    dd framebuffer_tag_end - framebuffer_tag_start  ; size
+    dd 0                                            ; width, 0 for no preference
+    dd 0                                            ; height
+    dd 32                                           ; depth
+framebuffer_tag_end:
+
     ; required end tags
+    align 8
     dw TYPE
     dw FLAGS
     dd SIZE
@@ -0,0 +1,165 @@
+/*
+ *  Kernel command line.
+ *
+ *  GRUB passes whatever follows the kernel path in its `multiboot2` line,
+ *  eg. `multiboot2 /boot/kernel.bin loglevel=debug noapic`. The options are
+ *  parsed once at boot into a `Config`, which subsystems read their settings
+ *  from. Options are separated by spaces and are either `key=value` or a
+ *  bare flag.
+ */
+
+use multiboot2::BootInformation;
+use spin::Once;
+
+use console::{self, Outputs};
+use log::{self, Level};
+use mb_tags;
+
+static CONFIG: Once<Config> = Once::new();
+
+// smallest `mem=` limit, below it there isn't enough room for the kernel,
+// its heap and the allocator bookkeeping
+const MIN_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+const DEFAULT_CONFIG: Config = Config {
+    log_level: None,
+    module_log_levels: [None; log::MAX_FILTERS],
+    console: None,
+    memory_limit: None,
+    noapic: false,
+    tests: None
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// `loglevel=LEVEL[,MODULE=LEVEL...]`, the maximum log level, optionally
+    /// followed by levels for single modules, eg. `memory::paging=trace`.
+    pub log_level: Option<Level>,
+    module_log_levels: [Option<(&'static str, Level)>; log::MAX_FILTERS],
+    /// `console=DEVICE[,DEVICE...]` with the devices `vga`, `serial` and
+    /// `fb`, where the console prints to.
+    pub console: Option<Outputs>,
+    /// `mem=SIZE`, the physical memory to use in bytes, with an optional K,
+    /// M or G suffix. Sizes below 16 MiB are invalid.
+    pub memory_limit: Option<usize>,
+    /// `noapic`, keeps using the 8259 PICs even if there is an APIC.
+    pub noapic: bool,
+    /// `test=NAME[,NAME...]`, the boot self tests to run out of `breakpoint`,
+    /// `paging` and `heap`. All of them run if it's not given.
+    tests: Option<&'static str>
+}
+
+impl Config {
+    fn parse(cmdline: &'static str) -> Config {
+        let mut config = DEFAULT_CONFIG;
+
+        for option in cmdline.split_whitespace() {
+            let (key, value) = match option.find('=') {
+                Some(index) => (&option[..index], Some(&option[index + 1..])),
+                None => (option, None)
+            };
+
+            let valid = match (key, value) {
+                ("loglevel", Some(value)) => config.parse_log_levels(value),
+                ("console", Some(value)) => {
+                    config.console = parse_outputs(value);
+                    config.console.is_some()
+                }
+                ("mem", Some(value)) => {
+                    config.memory_limit = parse_size(value).filter(|&limit| limit >= MIN_MEMORY_LIMIT);
+                    config.memory_limit.is_some()
+                }
+                ("noapic", None) => {
+                    config.noapic = true;
+                    true
+                }
+                ("test", Some(value)) => {
+                    config.tests = Some(value);
+                    true
+                }
+                _ => false
+            };
+
+            if !valid {
+                warn!("ignoring invalid option '{}'", option);
+            }
+        }
+
+        config
+    }
+
+    fn parse_log_levels(&mut self, value: &'static str) -> bool {
+        let mut module_count = 0;
+        for item in value.split(',') {
+            match item.find('=') {
+                Some(index) => {
+                    if module_count == log::MAX_FILTERS {
+                        return false;
+                    }
+                    match Level::from_name(&item[index + 1..]) {
+                        Some(level) => self.module_log_levels[module_count] = Some((&item[..index], level)),
+                        None => return false
+                    }
+                    module_count += 1;
+                }
+                None => match Level::from_name(item) {
+                    Some(level) => self.log_level = Some(level),
+                    None => return false
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns whether the boot self test `name` should run.
+    pub fn run_test(&self, name: &str) -> bool {
+        self.tests.map_or(true, |tests| tests.split(',').any(|test| test == name))
+    }
+
+    /// The modules with their own log level, set with `loglevel=`.
+    pub fn module_log_levels(&self) -> impl Iterator<Item = &(&'static str, Level)> {
+        self.module_log_levels.iter().filter_map(|level| level.as_ref())
+    }
+}
+
+/// Parses the command line passed by the bootloader. Logs invalid options and
+/// otherwise ignores them.
+pub fn init(boot_info: &BootInformation) -> &'static Config {
+    CONFIG.call_once(|| {
+        let cmdline = mb_tags::command_line(boot_info);
+        info!("command line: '{}'", cmdline);
+        Config::parse(cmdline)
+    })
+}
+
+/// The parsed command line, or the defaults if `init` hasn't run yet.
+pub fn config() -> &'static Config {
+    CONFIG.try().unwrap_or(&DEFAULT_CONFIG)
+}
+
+fn parse_outputs(value: &str) -> Option<Outputs> {
+    let mut outputs = Outputs::empty();
+    for name in value.split(',') {
+        let output = match name {
+            "vga" => console::VGA,
+            "serial" => console::SERIAL,
+            "fb" => console::FRAMEBUFFER,
+            _ => return None
+        };
+        outputs = outputs | output;
+    }
+    Some(outputs)
+}
+
+// a byte count with an optional binary K, M or G suffix
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 10),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 20),
+        Some('G') | Some('g') => (&value[..value.len() - 1], 30),
+        _ => (value, 0)
+    };
+
+    let number: usize = digits.parse().ok()?;
+    number.checked_mul(1 << shift)
+}
@@ -0,0 +1,71 @@
+/*
+ *  Kernel console.
+ *
+ *  Everything printed with print! and println! goes to every enabled output
+ *  device, so the same log shows up on screen and on the serial port.
+ */
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cmdline;
+use framebuffer;
+use interrupts::without_interrupts;
+use serial;
+use vga_buffer;
+
+bitflags! {
+    pub flags Outputs: u8 {
+        const VGA =         1 << 0,
+        const SERIAL =      1 << 1,
+        const FRAMEBUFFER = 1 << 2
+    }
+}
+
+static OUTPUTS: AtomicUsize = AtomicUsize::new(0b11);
+
+macro_rules! print {
+    ($($arg:tt)*) => ({
+        $crate::console::print(format_args!($($arg)*));
+    });
+}
+
+macro_rules! println {
+    ($fmt:expr) => (print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Switches to the outputs chosen on the command line, if any.
+pub fn init() {
+    if let Some(outputs) = cmdline::config().console {
+        set_outputs(outputs);
+    }
+}
+
+/// Chooses the devices the console writes to. VGA and serial are enabled by
+/// default, the framebuffer only once it has been set up.
+pub fn set_outputs(outputs: Outputs) {
+    OUTPUTS.store(outputs.bits() as usize, Ordering::Relaxed);
+}
+
+pub fn outputs() -> Outputs {
+    Outputs::from_bits_truncate(OUTPUTS.load(Ordering::Relaxed) as u8)
+}
+
+// interrupt handlers print too, so none may run while an output is locked
+pub fn print(args: fmt::Arguments) {
+    let outputs = outputs();
+    without_interrupts(|| {
+        if outputs.contains(VGA) {
+            vga_buffer::WRITER.lock().write_fmt(args).unwrap();
+        }
+        if outputs.contains(FRAMEBUFFER) {
+            if let Some(console) = framebuffer::console() {
+                console.lock().write_fmt(args).unwrap();
+            }
+        }
+        if outputs.contains(SERIAL) {
+            serial::COM1.lock().write_fmt(args).unwrap();
+        }
+    });
+}
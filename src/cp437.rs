@@ -0,0 +1,53 @@
+/*
+ *  Translation between Unicode and code page 437, the character set of the
+ *  VGA text mode font.
+ */
+
+/// Glyph shown for characters code page 437 has no glyph for.
+pub const PLACEHOLDER: u8 = 0xFE;
+
+// the glyphs of bytes 0x01 to 0x1F, which double as control characters
+const LOW_GLYPHS: &str = "☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+
+// the glyphs of bytes 0x80 to 0xFF
+const HIGH_GLYPHS: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+                           ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+                           αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+// characters which look close enough to an existing glyph
+const ALIASES: [(char, u8); 6] = [
+    ('β', 0xE1), ('μ', 0xE6), ('\u{2126}', 0xEA), ('∅', 0xED), ('∈', 0xEE), ('⌂', 0x7F)
+];
+
+/// Returns the code page 437 byte for `c`, or `None` if it has no glyph.
+/// ASCII maps to itself.
+pub fn from_char(c: char) -> Option<u8> {
+    if (c as u32) < 0x80 {
+        return Some(c as u8);
+    }
+
+    if let Some(index) = HIGH_GLYPHS.chars().position(|glyph| glyph == c) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = LOW_GLYPHS.chars().position(|glyph| glyph == c) {
+        return Some(0x01 + index as u8);
+    }
+    ALIASES.iter().find(|&&(alias, _)| alias == c).map(|&(_, byte)| byte)
+}
+
+/// Like `from_char`, but returns `PLACEHOLDER` for characters without a glyph.
+pub fn from_char_or_placeholder(c: char) -> u8 {
+    from_char(c).unwrap_or(PLACEHOLDER)
+}
+
+/// Returns the character shown for a code page 437 byte. Control characters
+/// are shown as their glyph, NUL as a space.
+pub fn to_char(byte: u8) -> char {
+    match byte {
+        0x00 => ' ',
+        0x01...0x1F => LOW_GLYPHS.chars().nth(byte as usize - 0x01).unwrap(),
+        0x7F => '⌂',
+        0x80...0xFF => HIGH_GLYPHS.chars().nth(byte as usize - 0x80).unwrap(),
+        _ => byte as char
+    }
+}
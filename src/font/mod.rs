@@ -0,0 +1,105 @@
+/*
+ *  Bitmap fonts for drawing text on a framebuffer.
+ *
+ *  Glyphs are stored one after the other, each row of a glyph padded to
+ *  whole bytes with the leftmost pixel in the most significant bit. Fonts
+ *  with a Unicode table say which characters each glyph shows, the others
+ *  are assumed to be in code page 437 order like the built-in font.
+ */
+
+use core::slice;
+
+use alloc::collections::BTreeMap;
+use multiboot2::BootInformation;
+
+use cp437;
+use mb_tags;
+use memory::{self, NO_EXECUTE};
+
+mod psf;
+
+// 256 glyphs of 8x16 pixels in code page 437 order
+static BUILTIN_FONT: &'static [u8] = include_bytes!("default8x16.psf");
+
+// command line of the boot module which holds the console font
+const FONT_MODULE: &str = "font";
+
+#[derive(Debug, Clone)]
+pub struct Font {
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+    glyph_count: usize,
+    glyphs: &'static [u8],
+    unicode: Option<BTreeMap<char, usize>>
+}
+
+impl Font {
+    /// The font compiled into the kernel.
+    pub fn builtin() -> Font {
+        psf::parse(BUILTIN_FONT).expect("Built-in font is not a valid PSF font")
+    }
+
+    /// Loads the PC Screen Font passed by the bootloader as a module with
+    /// the command line `font`, eg. `module2 /boot/font.psf font` in GRUB.
+    /// Returns `None` if there is no such module or the font is malformed.
+    pub fn from_module(boot_info: &BootInformation) -> Option<Font> {
+        let module = mb_tags::modules(boot_info).find(|module| module.cmdline == FONT_MODULE)?;
+        if module.size() == 0 {
+            println!("font module is empty");
+            return None;
+        }
+
+        memory::identity_map(module.start, module.size(), NO_EXECUTE);
+        let data = unsafe { slice::from_raw_parts(module.start as *const u8, module.size()) };
+
+        let font = psf::parse(data);
+        if font.is_none() {
+            println!("font module is not a valid PSF font");
+        }
+        font
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        self.bytes_per_row
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    /// Index of the glyph showing `c`, or of a placeholder glyph if the
+    /// font has none for it.
+    pub fn glyph_index(&self, c: char) -> usize {
+        match self.unicode {
+            Some(ref unicode) => {
+                unicode.get(&c)
+                    .or_else(|| unicode.get(&'\u{FFFD}'))
+                    .or_else(|| unicode.get(&'?'))
+                    .cloned()
+                    .unwrap_or(0)
+            }
+            None => cp437::from_char_or_placeholder(c) as usize
+        }
+    }
+
+    /// Bitmap of the glyph at `index`, `height` rows of `bytes_per_row`
+    /// bytes.
+    pub fn glyph(&self, index: usize) -> &'static [u8] {
+        let index = if index < self.glyph_count { index } else { 0 };
+        let size = self.glyph_size();
+        &self.glyphs[index * size..(index + 1) * size]
+    }
+
+    fn glyph_size(&self) -> usize {
+        self.bytes_per_row * self.height
+    }
+}
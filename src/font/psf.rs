@@ -0,0 +1,162 @@
+/*
+ *  PC Screen Font parser.
+ *
+ *  PSF1 fonts are 8 pixels wide with 256 or 512 glyphs, described by a 4
+ *  byte header directly followed by the glyph bitmaps. PSF2 has a longer
+ *  header which allows any size and number of glyphs. Both can end with a
+ *  Unicode table listing the characters of every glyph in turn, as 16 bit
+ *  code points in PSF1 and as UTF-8 in PSF2.
+ */
+
+use core::{ptr, str};
+
+use alloc::collections::BTreeMap;
+
+use font::Font;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_WIDTH: usize = 8;
+
+// PSF1 mode bits
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 0x04;
+
+// end of a glyph's entry and start of its multi character sequences
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_FLAG_HAS_TABLE: u32 = 0x01;
+
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+
+/// Parses a PSF1 or PSF2 font, returns `None` if `data` is neither or is
+/// truncated.
+pub fn parse(data: &'static [u8]) -> Option<Font> {
+    if data.starts_with(&PSF1_MAGIC) {
+        parse_psf1(data)
+    } else if data.starts_with(&PSF2_MAGIC) {
+        parse_psf2(data)
+    } else {
+        None
+    }
+}
+
+fn parse_psf1(data: &'static [u8]) -> Option<Font> {
+    if data.len() < PSF1_HEADER_SIZE {
+        return None;
+    }
+
+    let mode = data[2];
+    let height = data[3] as usize;
+    let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+    let glyphs_end = PSF1_HEADER_SIZE + glyph_count * height;
+    if height == 0 || data.len() < glyphs_end {
+        return None;
+    }
+
+    let unicode = if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) != 0 {
+        Some(psf1_unicode_table(&data[glyphs_end..], glyph_count))
+    } else {
+        None
+    };
+
+    Some(Font {
+        width: PSF1_WIDTH,
+        height: height,
+        bytes_per_row: 1,
+        glyph_count: glyph_count,
+        glyphs: &data[PSF1_HEADER_SIZE..glyphs_end],
+        unicode: unicode
+    })
+}
+
+fn parse_psf2(data: &'static [u8]) -> Option<Font> {
+    if data.len() < PSF2_HEADER_SIZE {
+        return None;
+    }
+
+    // magic and version, then little endian sizes
+    let field = |index: usize| unsafe { ptr::read_unaligned(data[index * 4..].as_ptr() as *const u32) } as usize;
+    let header_size = field(2);
+    let flags = field(3) as u32;
+    let glyph_count = field(4);
+    let glyph_size = field(5);
+    let height = field(6);
+    let width = field(7);
+
+    let bytes_per_row = (width + 7) / 8;
+    if width == 0 || height == 0 || glyph_count == 0 || glyph_size != bytes_per_row * height {
+        return None;
+    }
+
+    let glyphs_end = glyph_count.checked_mul(glyph_size)?.checked_add(header_size)?;
+    if header_size < PSF2_HEADER_SIZE || data.len() < glyphs_end {
+        return None;
+    }
+
+    let unicode = if flags & PSF2_FLAG_HAS_TABLE != 0 {
+        Some(psf2_unicode_table(&data[glyphs_end..], glyph_count))
+    } else {
+        None
+    };
+
+    Some(Font {
+        width: width,
+        height: height,
+        bytes_per_row: bytes_per_row,
+        glyph_count: glyph_count,
+        glyphs: &data[header_size..glyphs_end],
+        unicode: unicode
+    })
+}
+
+// maps the single characters of each glyph entry to the glyph, sequences of
+// combining characters can't be drawn in a single cell and are skipped
+fn psf1_unicode_table(table: &[u8], glyph_count: usize) -> BTreeMap<char, usize> {
+    let mut unicode = BTreeMap::new();
+    let mut glyph = 0;
+    let mut in_sequence = false;
+
+    for entry in table.chunks(2).filter(|entry| entry.len() == 2) {
+        if glyph >= glyph_count {
+            break;
+        }
+
+        match entry[0] as u16 | (entry[1] as u16) << 8 {
+            PSF1_SEPARATOR => {
+                glyph += 1;
+                in_sequence = false;
+            }
+            PSF1_START_SEQUENCE => in_sequence = true,
+            code_point if !in_sequence => {
+                if let Some(c) = ::core::char::from_u32(code_point as u32) {
+                    unicode.entry(c).or_insert(glyph);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    unicode
+}
+
+fn psf2_unicode_table(table: &[u8], glyph_count: usize) -> BTreeMap<char, usize> {
+    let mut unicode = BTreeMap::new();
+
+    for (glyph, entry) in table.split(|&byte| byte == PSF2_SEPARATOR).take(glyph_count).enumerate() {
+        let singles = entry.split(|&byte| byte == PSF2_START_SEQUENCE).next().unwrap_or(&[]);
+        if let Ok(chars) = str::from_utf8(singles) {
+            for c in chars.chars() {
+                unicode.entry(c).or_insert(glyph);
+            }
+        }
+    }
+
+    unicode
+}
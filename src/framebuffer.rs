@@ -0,0 +1,378 @@
+/*
+ *  Text console on a linear framebuffer.
+ *
+ *  Machines booted through UEFI, or by GRUB in a graphics mode, have no VGA
+ *  text buffer. The bootloader describes a framebuffer instead, which we
+ *  draw characters into with a bitmap font, either the built-in one or a
+ *  PC Screen Font passed as a boot module. Escape sequences are handled by
+ *  the same `ansi::Terminal` as in the VGA writer.
+ */
+
+use core::cmp::min;
+use core::fmt;
+use core::ptr;
+
+use alloc::vec::Vec;
+use multiboot2::BootInformation;
+use spin::{Mutex, Once};
+
+use ansi::{self, Attributes, Terminal};
+use cp437;
+use font::Font;
+use interrupts::without_interrupts;
+use mb_tags::{self, TAG_FRAMEBUFFER};
+use memory::{self, PhysicalAddress, WRITABLE, NO_CACHE, NO_EXECUTE};
+use vga_buffer::{Color, DEFAULT_FOREGROUND, DEFAULT_BACKGROUND};
+
+// framebuffer types of the multiboot2 framebuffer tag
+const TYPE_RGB: u8 = 1;
+
+// underline cursor in the bottom two pixel rows of a cell
+const CURSOR_HEIGHT: usize = 2;
+
+// the standard VGA palette, indexed by `Color`
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xAA), (0x00, 0xAA, 0x00), (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00), (0xAA, 0x00, 0xAA), (0xAA, 0x55, 0x00), (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xFF), (0x55, 0xFF, 0x55), (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55), (0xFF, 0x55, 0xFF), (0xFF, 0xFF, 0x55), (0xFF, 0xFF, 0xFF)
+];
+
+static CONSOLE: Once<Mutex<Console>> = Once::new();
+
+/// Position and width in bits of one color channel in a pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorField {
+    pub position: u8,
+    pub size: u8
+}
+
+impl ColorField {
+    // scales an 8 bit channel value to the field
+    fn encode(&self, value: u8) -> u32 {
+        let value = value as u32;
+        let value = if self.size <= 8 { value >> (8 - self.size) } else { value << (self.size - 8) };
+        value << self.position
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    pub address: PhysicalAddress,
+    /// Bytes per line of pixels.
+    pub pitch: usize,
+    pub width: usize,
+    pub height: usize,
+    pub bpp: u8,
+    pub red: ColorField,
+    pub green: ColorField,
+    pub blue: ColorField
+}
+
+impl FramebufferInfo {
+    /// Reads the framebuffer tag, returns `None` if the bootloader didn't set
+    /// up a direct color framebuffer with 16, 24 or 32 bits per pixel.
+    pub fn parse(boot_info: &BootInformation) -> Option<FramebufferInfo> {
+        let tag = mb_tags::find_tag(boot_info, TAG_FRAMEBUFFER)?;
+        let data = tag.data_address();
+
+        // address, pitch, width, height, bpp and type, then GRUB puts a 16
+        // bit reserved field before the color info
+        unsafe {
+            let byte = |offset: usize| ptr::read_unaligned((data + offset) as *const u8);
+            if byte(21) != TYPE_RGB {
+                return None;
+            }
+
+            let info = FramebufferInfo {
+                address: ptr::read_unaligned(data as *const u64) as PhysicalAddress,
+                pitch: ptr::read_unaligned((data + 8) as *const u32) as usize,
+                width: ptr::read_unaligned((data + 12) as *const u32) as usize,
+                height: ptr::read_unaligned((data + 16) as *const u32) as usize,
+                bpp: byte(20),
+                red: ColorField { position: byte(24), size: byte(25) },
+                green: ColorField { position: byte(26), size: byte(27) },
+                blue: ColorField { position: byte(28), size: byte(29) }
+            };
+
+            match info.bytes_per_pixel() {
+                2...4 => Some(info),
+                _ => None
+            }
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        (self.bpp as usize + 7) / 8
+    }
+
+    // the value of a pixel with the given color
+    fn encode(&self, (red, green, blue): (u8, u8, u8)) -> u32 {
+        self.red.encode(red) | self.green.encode(green) | self.blue.encode(blue)
+    }
+}
+
+/// Maps the framebuffer set up by the bootloader and starts a text console
+/// on it. Returns false if there is none, eg. when booted in VGA text mode.
+/// Needs the heap, so it can't be used before `memory::init`.
+pub fn init(boot_info: &BootInformation) -> bool {
+    let info = match FramebufferInfo::parse(boot_info) {
+        Some(info) => info,
+        None => return false
+    };
+
+    // pixels have to reach the screen instead of sitting in the cache
+    memory::identity_map(info.address, info.pitch * info.height, WRITABLE | NO_CACHE | NO_EXECUTE);
+
+    let font = Font::from_module(boot_info).unwrap_or_else(|| {
+        println!("using the built-in font");
+        Font::builtin()
+    });
+    println!("framebuffer: {}x{} with {} bits per pixel at 0x{:x}, {}x{} font with {} glyphs",
+             info.width, info.height, info.bpp, info.address, font.width(), font.height(), font.glyph_count());
+
+    let console = CONSOLE.call_once(|| Mutex::new(Console::new(info, font)));
+    without_interrupts(|| console.lock().clear());
+    true
+}
+
+/// The framebuffer console, if `init` found a framebuffer.
+pub fn console() -> Option<&'static Mutex<Console>> {
+    CONSOLE.try()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    glyph: usize,
+    foreground: Color,
+    background: Color
+}
+
+pub struct Console {
+    info: FramebufferInfo,
+    font: Font,
+    columns: usize,
+    rows: usize,
+    // characters on the screen, so scrolling doesn't have to read back the
+    // uncached framebuffer
+    cells: Vec<Cell>,
+    row_position: usize,
+    column_position: usize,
+    saved_position: (usize, usize),
+    attributes: Attributes,
+    parser: ansi::Parser,
+    // the cell the cursor is drawn in
+    cursor: Option<(usize, usize)>,
+    // pixel values of the palette colors
+    pixels: [u32; 16]
+}
+
+impl Console {
+    fn new(info: FramebufferInfo, font: Font) -> Console {
+        let columns = info.width / font.width();
+        let rows = info.height / font.height();
+        let blank = Cell { glyph: font.glyph_index(' '), foreground: DEFAULT_FOREGROUND, background: DEFAULT_BACKGROUND };
+
+        let mut pixels = [0; 16];
+        for (pixel, &color) in pixels.iter_mut().zip(PALETTE.iter()) {
+            *pixel = info.encode(color);
+        }
+
+        Console {
+            info: info,
+            font: font,
+            columns: columns,
+            rows: rows,
+            cells: vec![blank; columns * rows],
+            row_position: 0,
+            column_position: 0,
+            saved_position: (0, 0),
+            attributes: Attributes::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+            parser: ansi::Parser::new(),
+            cursor: None,
+            pixels: pixels
+        }
+    }
+
+    /// Writes a string, interpreting ANSI escape sequences in it. Characters
+    /// the font has no glyph for are shown as a placeholder.
+    pub fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.process_char(c);
+        }
+        self.update_cursor();
+    }
+
+    /// Moves the cursor, the next character is written at `(row, col)`.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        assert!(row < self.rows && col < self.columns, "Position ({}, {}) outside of the screen", row, col);
+        self.row_position = row;
+        self.column_position = col;
+        self.update_cursor();
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    /// Blanks the whole screen and moves the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        // draw every cell, the framebuffer may still show the boot logo
+        let blank = self.blank();
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                self.cells[row * self.columns + col] = blank;
+                self.draw_cell(row, col);
+            }
+        }
+
+        self.set_position(0, 0);
+    }
+
+    // moves the underline cursor to the current position
+    fn update_cursor(&mut self) {
+        let position = (self.row_position, min(self.column_position, self.columns - 1));
+        if self.cursor == Some(position) {
+            return;
+        }
+
+        let old = self.cursor;
+        self.cursor = Some(position);
+        if let Some((row, col)) = old {
+            self.draw_cell(row, col);
+        }
+        self.draw_cell(position.0, position.1);
+    }
+
+    fn blank(&self) -> Cell {
+        let &Attributes { foreground, background, .. } = &self.attributes;
+        Cell { glyph: self.font.glyph_index(' '), foreground: foreground, background: background }
+    }
+
+    fn set_cell(&mut self, row: usize, col: usize, cell: Cell) {
+        let index = row * self.columns + col;
+        if self.cells[index] != cell {
+            self.cells[index] = cell;
+            self.draw_cell(row, col);
+        }
+    }
+
+    fn draw_cell(&self, row: usize, col: usize) {
+        let cell = self.cells[row * self.columns + col];
+        let foreground = self.pixels[cell.foreground as usize];
+        let background = self.pixels[cell.background as usize];
+
+        let (width, height) = (self.font.width(), self.font.height());
+        let bytes_per_row = self.font.bytes_per_row();
+        let bytes_per_pixel = self.info.bytes_per_pixel();
+        let glyph = self.font.glyph(cell.glyph);
+        let cursor = self.cursor == Some((row, col));
+
+        let cell_start = self.info.address + row * height * self.info.pitch + col * width * bytes_per_pixel;
+        for y in 0..height {
+            let bits = &glyph[y * bytes_per_row..(y + 1) * bytes_per_row];
+            let underline = cursor && y >= height - CURSOR_HEIGHT;
+            let line = cell_start + y * self.info.pitch;
+            for x in 0..width {
+                let set = bits[x / 8] & (0x80 >> (x % 8)) != 0;
+                let pixel = if set || underline { foreground } else { background };
+                self.put_pixel(line + x * bytes_per_pixel, pixel);
+            }
+        }
+    }
+
+    fn put_pixel(&self, address: usize, pixel: u32) {
+        unsafe {
+            match self.info.bytes_per_pixel() {
+                4 => ptr::write_volatile(address as *mut u32, pixel),
+                3 => {
+                    ptr::write_volatile(address as *mut u8, pixel as u8);
+                    ptr::write_volatile((address + 1) as *mut u8, (pixel >> 8) as u8);
+                    ptr::write_volatile((address + 2) as *mut u8, (pixel >> 16) as u8);
+                }
+                _ => ptr::write_volatile(address as *mut u16, pixel as u16)
+            }
+        }
+    }
+}
+
+impl Terminal for Console {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.rows, self.columns)
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        self.position()
+    }
+
+    fn move_cursor(&mut self, row: usize, column: usize) {
+        self.row_position = row;
+        self.column_position = column;
+    }
+
+    fn saved_cursor(&mut self) -> &mut (usize, usize) {
+        &mut self.saved_position
+    }
+
+    fn parser(&mut self) -> &mut ansi::Parser {
+        &mut self.parser
+    }
+
+    fn attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+
+    fn write_character(&mut self, c: char) {
+        if self.column_position >= self.columns {
+            self.write_new_line();
+        }
+
+        // control characters are shown as their code page 437 glyph
+        let c = if (c as u32) < 0x80 { cp437::to_char(c as u8) } else { c };
+        let cell = Cell {
+            glyph: self.font.glyph_index(c),
+            foreground: self.attributes.text_color(),
+            background: self.attributes.background
+        };
+
+        let (row, col) = (self.row_position, self.column_position);
+        self.set_cell(row, col, cell);
+        self.column_position += 1;
+    }
+
+    fn write_new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position + 1 < self.rows {
+            self.row_position += 1;
+            return;
+        }
+
+        // only cells which change are drawn again, which skips most of the
+        // blank space on a typical screen
+        for row in 1..self.rows {
+            for col in 0..self.columns {
+                let cell = self.cells[row * self.columns + col];
+                self.set_cell(row - 1, col, cell);
+            }
+        }
+
+        let columns = self.columns;
+        let last_row = self.rows - 1;
+        self.clear_columns(last_row, 0, columns);
+    }
+
+    // blanks the columns [start, end) of a row
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank();
+        for col in start..end {
+            self.set_cell(row, col, blank);
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_str(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+/*
+ *  Local APIC and I/O APIC.
+ *
+ *  The I/O APICs found in the ACPI MADT take over the ISA IRQs from the 8259
+ *  PICs. ISA IRQ n is delivered on the same vector the remapped PICs used,
+ *  so the dispatch stubs stay the same and only masking and end of interrupt
+ *  go through the APICs instead.
+ */
+
+use core::ptr;
+
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+use x86::shared::msr::{IA32_APIC_BASE, rdmsr, wrmsr};
+
+use acpi::madt::Madt;
+use interrupts::idt::{Idt, ExceptionStackFrame};
+use interrupts::irq::IRQ_COUNT;
+use interrupts::pic::PIC_1_OFFSET;
+use memory::{self, PhysicalAddress, PAGE_SIZE, WRITABLE, NO_CACHE, NO_EXECUTE};
+
+/// Vector of the local APIC's spurious interrupts, which need no EOI.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+/// Vector the local APIC timer is programmed to.
+pub const TIMER_VECTOR: u8 = 0xF0;
+
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// local APIC registers, as offsets into its MMIO page
+const LAPIC_ID: usize = 0x20;
+const LAPIC_VERSION: usize = 0x30;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xB0;
+const LAPIC_SPURIOUS: usize = 0xF0;
+const LAPIC_IN_SERVICE: usize = 0x100;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_ERROR: usize = 0x370;
+
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+
+// I/O APIC registers are accessed indirectly through a select and a window register
+const IOAPIC_SELECT: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+pub struct LocalApic {
+    base: usize
+}
+
+impl LocalApic {
+    pub fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(LAPIC_VERSION) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(LAPIC_EOI, 0);
+    }
+
+    /// Checks whether the local APIC is handling an interrupt on `vector`.
+    pub fn in_service(&self, vector: u8) -> bool {
+        // eight 32 bit registers, 16 bytes apart
+        self.read(LAPIC_IN_SERVICE + vector as usize / 32 * 0x10) & 1 << (vector % 32) != 0
+    }
+
+    pub fn read(&self, register: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + register) as *const u32) }
+    }
+
+    pub fn write(&self, register: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + register) as *mut u32, value) }
+    }
+
+    fn enable(&self) {
+        // accept every priority, the legacy PIC input on LINT0 is masked
+        // since the I/O APIC delivers the ISA IRQs now
+        self.write(LAPIC_TASK_PRIORITY, 0);
+        self.write(LAPIC_LVT_LINT0, LVT_MASKED);
+        self.write(LAPIC_LVT_ERROR, LVT_MASKED);
+        self.write(LAPIC_SPURIOUS, LAPIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+}
+
+struct IoApic {
+    base: usize,
+    gsi_base: u32,
+    entry_count: u32
+}
+
+impl IoApic {
+    fn new(base: usize, gsi_base: u32) -> IoApic {
+        let mut io_apic = IoApic { base: base, gsi_base: gsi_base, entry_count: 0 };
+        io_apic.entry_count = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+        io_apic
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entry_count
+    }
+
+    fn redirection(&self, gsi: u32) -> u64 {
+        let register = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+    fn set_redirection(&mut self, gsi: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        // mask the entry while it's half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + IOAPIC_SELECT) as *mut u32, register);
+            ptr::read_volatile((self.base + IOAPIC_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + IOAPIC_SELECT) as *mut u32, register);
+            ptr::write_volatile((self.base + IOAPIC_WINDOW) as *mut u32, value);
+        }
+    }
+}
+
+struct Apic {
+    local: LocalApic,
+    io_apics: Mutex<Vec<IoApic>>,
+    // global system interrupt each ISA IRQ is wired to, if any
+    isa_routes: [Option<u32>; IRQ_COUNT]
+}
+
+static APIC: Once<Apic> = Once::new();
+static TIMER_HANDLER: Once<fn()> = Once::new();
+
+/// Enables the local APIC and routes the ISA IRQs through the I/O APICs,
+/// all of them masked. Returns false if the CPU has no APIC or ACPI doesn't
+/// describe any I/O APIC, in which case nothing is changed.
+pub fn init() -> bool {
+    if !cpu_has_apic() {
+        println!("apic: not supported by the CPU");
+        return false;
+    }
+
+    let madt = match Madt::parse() {
+        Some(madt) => madt,
+        None => {
+            println!("apic: no MADT found");
+            return false;
+        }
+    };
+    if madt.io_apics.is_empty() {
+        println!("apic: no I/O APIC found");
+        return false;
+    }
+
+    let local = unsafe {
+        let base = rdmsr(IA32_APIC_BASE);
+        wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+        LocalApic { base: map_registers((base & APIC_BASE_ADDRESS_MASK) as PhysicalAddress) }
+    };
+    local.enable();
+
+    let mut io_apics = Vec::new();
+    for entry in madt.io_apics.iter() {
+        let mut io_apic = IoApic::new(map_registers(entry.address), entry.gsi_base);
+        for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entry_count {
+            io_apic.set_redirection(gsi, REDIRECTION_MASKED);
+        }
+        io_apics.push(io_apic);
+    }
+
+    let mut isa_routes = [None; IRQ_COUNT];
+    for irq in 0..IRQ_COUNT as u8 {
+        let (gsi, flags) = isa_route(&madt, irq);
+        let gsi = match gsi {
+            Some(gsi) => gsi,
+            None => continue
+        };
+
+        match io_apics.iter_mut().find(|io_apic| io_apic.handles(gsi)) {
+            Some(io_apic) => {
+                let entry = (PIC_1_OFFSET + irq) as u64 | flags | REDIRECTION_MASKED
+                    | (local.id() as u64) << 56;
+                io_apic.set_redirection(gsi, entry);
+                isa_routes[irq as usize] = Some(gsi);
+            }
+            None => println!("apic: no I/O APIC handles GSI {} for IRQ {}", gsi, irq)
+        }
+    }
+
+    println!("apic: local APIC {} (version 0x{:x}) at 0x{:x}, {} I/O APIC(s), {} processor(s)",
+             local.id(), local.version(), local.base, io_apics.len(), madt.local_apic_ids.len());
+
+    APIC.call_once(|| Apic { local: local, io_apics: Mutex::new(io_apics), isa_routes: isa_routes });
+    true
+}
+
+pub fn is_enabled() -> bool {
+    APIC.try().is_some()
+}
+
+pub fn local_apic() -> Option<&'static LocalApic> {
+    APIC.try().map(|apic| &apic.local)
+}
+
+pub fn end_of_interrupt() {
+    if let Some(apic) = APIC.try() {
+        apic.local.end_of_interrupt();
+    }
+}
+
+pub fn mask(irq: u8) {
+    set_masked(irq, true);
+}
+
+pub fn unmask(irq: u8) {
+    set_masked(irq, false);
+}
+
+/// Sets the function called on every local APIC timer interrupt. Can only be
+/// set once.
+pub fn set_timer_handler(handler: fn()) {
+    TIMER_HANDLER.call_once(|| handler);
+}
+
+pub fn set_handlers(idt: &mut Idt) {
+    idt.interrupts[SPURIOUS_VECTOR as usize - 32].set_handler_fn(spurious_handler);
+    idt.interrupts[TIMER_VECTOR as usize - 32].set_handler_fn(timer_handler);
+}
+
+extern "x86-interrupt" fn timer_handler(_stack_frame: &mut ExceptionStackFrame) {
+    if let Some(handler) = TIMER_HANDLER.try() {
+        handler();
+    }
+    end_of_interrupt();
+}
+
+// the local APIC raises this if an interrupt goes away before it's
+// acknowledged, there's nothing to handle and it must not get an EOI
+extern "x86-interrupt" fn spurious_handler(_stack_frame: &mut ExceptionStackFrame) {}
+
+fn set_masked(irq: u8, masked: bool) {
+    let apic = APIC.try().expect("APIC not initialized");
+    let gsi = match apic.isa_routes[irq as usize] {
+        Some(gsi) => gsi,
+        None => return
+    };
+
+    let mut io_apics = apic.io_apics.lock();
+    let io_apic = io_apics.iter_mut().find(|io_apic| io_apic.handles(gsi)).unwrap();
+    let entry = io_apic.redirection(gsi);
+    io_apic.set_redirection(gsi, if masked { entry | REDIRECTION_MASKED } else { entry & !REDIRECTION_MASKED });
+}
+
+// ISA IRQs are identity mapped to global system interrupts, active high and
+// edge triggered, unless the MADT says otherwise. An IRQ whose GSI another
+// override took isn't connected at all.
+fn isa_route(madt: &Madt, irq: u8) -> (Option<u32>, u64) {
+    if let Some(interrupt_override) = madt.interrupt_override(irq) {
+        let mut flags = 0;
+        if interrupt_override.active_low() {
+            flags |= REDIRECTION_ACTIVE_LOW;
+        }
+        if interrupt_override.level_triggered() {
+            flags |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+        return (Some(interrupt_override.gsi), flags);
+    }
+
+    if madt.overrides.iter().any(|o| o.gsi == irq as u32) {
+        (None, 0)
+    } else {
+        (Some(irq as u32), 0)
+    }
+}
+
+fn map_registers(addr: PhysicalAddress) -> usize {
+    memory::identity_map(addr, PAGE_SIZE, WRITABLE | NO_CACHE | NO_EXECUTE);
+    addr
+}
+
+fn cpu_has_apic() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    unsafe { __cpuid(1).edx & (1 << 9) != 0 }
+}
@@ -0,0 +1,171 @@
+/*
+ *  Handlers for the 32 architectural CPU exceptions.
+ *
+ *  Breakpoint and debug exceptions are reported and then resumed, everything
+ *  else prints as much as we know about the fault and halts the CPU. Reports
+ *  are written to the serial port directly, since the faulting code may hold
+ *  the console locks.
+ */
+
+use interrupts::idt::{Idt, ExceptionStackFrame};
+use interrupts::{DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX};
+use memory;
+use serial;
+use x86::shared::control_regs;
+
+bitflags! {
+    pub flags PageFaultErrorCode: u64 {
+        const PROTECTION_VIOLATION = 1 << 0,
+        const CAUSED_BY_WRITE =      1 << 1,
+        const USER_MODE =            1 << 2,
+        const MALFORMED_TABLE =      1 << 3,
+        const INSTRUCTION_FETCH =    1 << 4,
+        const PROTECTION_KEY =       1 << 5,
+        const SHADOW_STACK =         1 << 6,
+        const SGX =                  1 << 15
+    }
+}
+
+// exception reports go straight to the serial port without taking any lock,
+// the exception may have interrupted code which holds the console
+macro_rules! report {
+    ($fmt:expr) => (unsafe { serial::force_print(format_args!(concat!($fmt, "\n"))) });
+    ($fmt:expr, $($arg:tt)*) => (unsafe { serial::force_print(format_args!(concat!($fmt, "\n"), $($arg)*)) });
+}
+
+// generates a handler which reports the exception and halts
+macro_rules! fatal_handler {
+    ($name:ident, $description:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut ExceptionStackFrame) {
+            report!("\nEXCEPTION: {}\n{}", $description, stack_frame);
+            hlt_loop();
+        }
+    };
+}
+
+// same as fatal_handler!, for exceptions which push an error code
+macro_rules! fatal_handler_with_error_code {
+    ($name:ident, $description:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut ExceptionStackFrame, error_code: u64) {
+            report!("\nEXCEPTION: {} (error code: 0x{:x})\n{}", $description, error_code, stack_frame);
+            hlt_loop();
+        }
+    };
+}
+
+pub fn set_handlers(idt: &mut Idt) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    idt.double_fault.set_handler_fn(double_fault_handler);
+    idt.coprocessor_segment_overrun.set_handler_fn(coprocessor_segment_overrun_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.reserved_15.set_handler_fn(reserved_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    for entry in idt.reserved_21_29.iter_mut() {
+        entry.set_handler_fn(reserved_handler);
+    }
+    idt.security_exception.set_handler_fn(security_exception_handler);
+    idt.reserved_31.set_handler_fn(reserved_handler);
+}
+
+/// Moves the handlers of exceptions which can't trust the current stack onto
+/// their Interrupt Stack Table stacks. The TSS has to be loaded before the
+/// IDT is.
+pub fn set_stack_indices(idt: &mut Idt) {
+    unsafe {
+        idt.double_fault.set_handler_fn(double_fault_handler)
+            .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(NMI_IST_INDEX);
+        idt.machine_check.set_handler_fn(machine_check_handler)
+            .set_stack_index(MACHINE_CHECK_IST_INDEX);
+    }
+}
+
+extern "x86-interrupt" fn debug_handler(stack_frame: &mut ExceptionStackFrame) {
+    report!("\nEXCEPTION: DEBUG\n{}", stack_frame);
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut ExceptionStackFrame) {
+    report!("\nEXCEPTION: BREAKPOINT\n{}", stack_frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(stack_frame: &mut ExceptionStackFrame, error_code: u64) {
+    let address = unsafe { control_regs::cr2() };
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+
+    if memory::is_stack_guard(address) {
+        report!("\nEXCEPTION: PAGE FAULT: kernel stack overflow at 0x{:x}", address);
+    } else {
+        report!("\nEXCEPTION: PAGE FAULT while {} 0x{:x}",
+                if error_code.contains(INSTRUCTION_FETCH) {
+                    "executing"
+                } else if error_code.contains(CAUSED_BY_WRITE) {
+                    "writing to"
+                } else {
+                    "reading from"
+                },
+                address);
+    }
+
+    report!("    {} in {} mode, error code: {:?}",
+            if error_code.contains(PROTECTION_VIOLATION) { "protection violation" } else { "page not present" },
+            if error_code.contains(USER_MODE) { "user" } else { "kernel" },
+            error_code);
+    report!("{}", stack_frame);
+    hlt_loop();
+}
+
+extern "x86-interrupt" fn double_fault_handler(stack_frame: &mut ExceptionStackFrame, error_code: u64) {
+    // a page fault on a guard page can't push its exception frame onto the
+    // overflowed stack, which turns it into a double fault
+    let address = unsafe { control_regs::cr2() };
+    if memory::is_stack_guard(address) {
+        report!("\nEXCEPTION: DOUBLE FAULT: kernel stack overflow at 0x{:x}", address);
+    } else {
+        report!("\nEXCEPTION: DOUBLE FAULT (error code: 0x{:x})", error_code);
+    }
+
+    report!("{}", stack_frame);
+    hlt_loop();
+}
+
+fatal_handler!(divide_error_handler, "DIVIDE ERROR");
+fatal_handler!(non_maskable_interrupt_handler, "NON-MASKABLE INTERRUPT");
+fatal_handler!(overflow_handler, "OVERFLOW");
+fatal_handler!(bound_range_exceeded_handler, "BOUND RANGE EXCEEDED");
+fatal_handler!(invalid_opcode_handler, "INVALID OPCODE");
+fatal_handler!(device_not_available_handler, "DEVICE NOT AVAILABLE");
+fatal_handler!(coprocessor_segment_overrun_handler, "COPROCESSOR SEGMENT OVERRUN");
+fatal_handler!(x87_floating_point_handler, "x87 FLOATING POINT");
+fatal_handler!(machine_check_handler, "MACHINE CHECK");
+fatal_handler!(simd_floating_point_handler, "SIMD FLOATING POINT");
+fatal_handler!(virtualization_handler, "VIRTUALIZATION");
+fatal_handler!(reserved_handler, "RESERVED");
+
+fatal_handler_with_error_code!(invalid_tss_handler, "INVALID TSS");
+fatal_handler_with_error_code!(segment_not_present_handler, "SEGMENT NOT PRESENT");
+fatal_handler_with_error_code!(stack_segment_fault_handler, "STACK SEGMENT FAULT");
+fatal_handler_with_error_code!(general_protection_fault_handler, "GENERAL PROTECTION FAULT");
+fatal_handler_with_error_code!(alignment_check_handler, "ALIGNMENT CHECK");
+fatal_handler_with_error_code!(security_exception_handler, "SECURITY EXCEPTION");
+
+pub fn hlt_loop() -> ! {
+    loop {
+        unsafe { asm!("hlt" :::: "volatile"); }
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ *  Global Descriptor Table and Task State Segment.
+ *
+ *  The GDT from boot.asm only has a code and a data segment. This one adds a
+ *  64-bit TSS descriptor, which is what gives us the Interrupt Stack Table:
+ *  known-good stacks the CPU switches to for selected exceptions.
+ */
+
+use core::mem::size_of;
+
+use interrupts::DescriptorTablePointer;
+
+// segment descriptor flags, same as the ones in boot.asm
+const SEG_READ_WRITE:   u64 = 1 << 41;
+const SEG_EXECUTABLE:   u64 = 1 << 43;
+const SEG_CODE_OR_DATA: u64 = 1 << 44;
+const SEG_PRESENT:      u64 = 1 << 47;
+const SEG_CODE_64_BIT:  u64 = 1 << 53;
+
+// system segment type of an available 64-bit TSS
+const SEG_TSS_AVAILABLE: u64 = 0b1001 << 40;
+
+const GDT_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    pub privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    pub interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    pub iomap_base: u16
+}
+
+impl TaskStateSegment {
+    pub fn new() -> TaskStateSegment {
+        TaskStateSegment {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            // no I/O permission bitmap
+            iomap_base: size_of::<TaskStateSegment>() as u16
+        }
+    }
+}
+
+pub enum Descriptor {
+    UserSegment(u64),
+    SystemSegment(u64, u64)
+}
+
+impl Descriptor {
+    pub fn kernel_code_segment() -> Descriptor {
+        Descriptor::UserSegment(SEG_READ_WRITE | SEG_EXECUTABLE | SEG_CODE_OR_DATA | SEG_PRESENT | SEG_CODE_64_BIT)
+    }
+
+    pub fn kernel_data_segment() -> Descriptor {
+        Descriptor::UserSegment(SEG_READ_WRITE | SEG_CODE_OR_DATA | SEG_PRESENT)
+    }
+
+    pub fn tss_segment(tss: &'static TaskStateSegment) -> Descriptor {
+        let ptr = tss as *const _ as u64;
+
+        let mut low = SEG_PRESENT | SEG_TSS_AVAILABLE;
+        // base, split over bits 16-39 and 56-63
+        low |= (ptr & 0xFF_FFFF) << 16;
+        low |= ((ptr >> 24) & 0xFF) << 56;
+        // limit
+        low |= (size_of::<TaskStateSegment>() - 1) as u64;
+
+        // upper 32 bits of the base
+        let high = ptr >> 32;
+
+        Descriptor::SystemSegment(low, high)
+    }
+}
+
+pub struct Gdt {
+    table: [u64; GDT_SIZE],
+    next_free: usize
+}
+
+impl Gdt {
+    pub fn new() -> Gdt {
+        Gdt {
+            // the first entry has to be the null descriptor
+            table: [0; GDT_SIZE],
+            next_free: 1
+        }
+    }
+
+    /// Adds the descriptor and returns the segment selector for it.
+    pub fn add_entry(&mut self, entry: Descriptor) -> u16 {
+        let index = match entry {
+            Descriptor::UserSegment(value) => self.push(value),
+            Descriptor::SystemSegment(low, high) => {
+                let index = self.push(low);
+                self.push(high);
+                index
+            }
+        };
+
+        // ring 0, global descriptor table
+        (index * 8) as u16
+    }
+
+    fn push(&mut self, value: u64) -> usize {
+        assert!(self.next_free < self.table.len(), "GDT full");
+        let index = self.next_free;
+        self.table[index] = value;
+        self.next_free += 1;
+        index
+    }
+
+    /// Loads the table into the GDTR. The table has to live forever, and the
+    /// segment registers have to be reloaded afterwards.
+    pub fn load(&'static self) {
+        let ptr = DescriptorTablePointer {
+            limit: (self.table.len() * size_of::<u64>() - 1) as u16,
+            base: self.table.as_ptr() as u64
+        };
+
+        unsafe {
+            asm!("lgdt ($0)" :: "r" (&ptr) : "memory");
+        }
+    }
+}
+
+/// Reloads CS by doing a far return to the next instruction.
+pub unsafe fn set_cs(selector: u16) {
+    asm!("pushq $0
+          leaq 1f(%rip), %rax
+          pushq %rax
+          lretq
+          1:" :: "ri" (selector as u64) : "rax" "memory" : "volatile");
+}
+
+pub unsafe fn load_data_segments(selector: u16) {
+    asm!("mov $0, %ds
+          mov $0, %es
+          mov $0, %ss" :: "r" (selector) : "memory" : "volatile");
+}
+
+pub unsafe fn load_tss(selector: u16) {
+    asm!("ltr $0" :: "r" (selector) : "memory" : "volatile");
+}
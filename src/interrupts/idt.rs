@@ -0,0 +1,209 @@
+/*
+ *  Interrupt Descriptor Table.
+ *
+ *  Every architectural exception gets its own typed field, so a handler with
+ *  the wrong signature for an exception (eg. missing the error code) is a
+ *  compile error instead of a corrupted stack.
+ */
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+use interrupts::DescriptorTablePointer;
+
+pub type HandlerFunc = extern "x86-interrupt" fn(&mut ExceptionStackFrame);
+pub type HandlerFuncWithErrCode = extern "x86-interrupt" fn(&mut ExceptionStackFrame, u64);
+
+/// The frame the CPU pushes onto the stack before calling a handler.
+#[repr(C)]
+pub struct ExceptionStackFrame {
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64
+}
+
+impl fmt::Display for ExceptionStackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    RIP:    0x{:016x}  CS: 0x{:04x}", self.instruction_pointer, self.code_segment)?;
+        writeln!(f, "    RSP:    0x{:016x}  SS: 0x{:04x}", self.stack_pointer, self.stack_segment)?;
+        write!(f, "    RFLAGS: 0x{:016x}", self.cpu_flags)
+    }
+}
+
+#[repr(C)]
+pub struct Idt {
+    pub divide_error: Entry<HandlerFunc>,
+    pub debug: Entry<HandlerFunc>,
+    pub non_maskable_interrupt: Entry<HandlerFunc>,
+    pub breakpoint: Entry<HandlerFunc>,
+    pub overflow: Entry<HandlerFunc>,
+    pub bound_range_exceeded: Entry<HandlerFunc>,
+    pub invalid_opcode: Entry<HandlerFunc>,
+    pub device_not_available: Entry<HandlerFunc>,
+    pub double_fault: Entry<HandlerFuncWithErrCode>,
+    pub coprocessor_segment_overrun: Entry<HandlerFunc>,
+    pub invalid_tss: Entry<HandlerFuncWithErrCode>,
+    pub segment_not_present: Entry<HandlerFuncWithErrCode>,
+    pub stack_segment_fault: Entry<HandlerFuncWithErrCode>,
+    pub general_protection_fault: Entry<HandlerFuncWithErrCode>,
+    pub page_fault: Entry<HandlerFuncWithErrCode>,
+    pub reserved_15: Entry<HandlerFunc>,
+    pub x87_floating_point: Entry<HandlerFunc>,
+    pub alignment_check: Entry<HandlerFuncWithErrCode>,
+    pub machine_check: Entry<HandlerFunc>,
+    pub simd_floating_point: Entry<HandlerFunc>,
+    pub virtualization: Entry<HandlerFunc>,
+    pub reserved_21_29: [Entry<HandlerFunc>; 9],
+    pub security_exception: Entry<HandlerFuncWithErrCode>,
+    pub reserved_31: Entry<HandlerFunc>,
+
+    /// Vectors 32 to 255, available for hardware and software interrupts.
+    pub interrupts: [Entry<HandlerFunc>; 256 - 32]
+}
+
+impl Idt {
+    pub fn new() -> Idt {
+        Idt {
+            divide_error: Entry::missing(),
+            debug: Entry::missing(),
+            non_maskable_interrupt: Entry::missing(),
+            breakpoint: Entry::missing(),
+            overflow: Entry::missing(),
+            bound_range_exceeded: Entry::missing(),
+            invalid_opcode: Entry::missing(),
+            device_not_available: Entry::missing(),
+            double_fault: Entry::missing(),
+            coprocessor_segment_overrun: Entry::missing(),
+            invalid_tss: Entry::missing(),
+            segment_not_present: Entry::missing(),
+            stack_segment_fault: Entry::missing(),
+            general_protection_fault: Entry::missing(),
+            page_fault: Entry::missing(),
+            reserved_15: Entry::missing(),
+            x87_floating_point: Entry::missing(),
+            alignment_check: Entry::missing(),
+            machine_check: Entry::missing(),
+            simd_floating_point: Entry::missing(),
+            virtualization: Entry::missing(),
+            reserved_21_29: [Entry::missing(); 9],
+            security_exception: Entry::missing(),
+            reserved_31: Entry::missing(),
+            interrupts: [Entry::missing(); 256 - 32]
+        }
+    }
+
+    /// Loads the table into the IDTR. The table has to live forever, since
+    /// the CPU keeps using it until another table is loaded.
+    pub fn load(&'static self) {
+        let ptr = DescriptorTablePointer {
+            limit: (size_of::<Idt>() - 1) as u16,
+            base: self as *const _ as u64
+        };
+
+        unsafe {
+            asm!("lidt ($0)" :: "r" (&ptr) : "memory");
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Entry<F> {
+    pointer_low: u16,
+    gdt_selector: u16,
+    options: EntryOptions,
+    pointer_middle: u16,
+    pointer_high: u32,
+    reserved: u32,
+    handler: PhantomData<F>
+}
+
+impl<F> Entry<F> {
+    fn missing() -> Entry<F> {
+        Entry {
+            pointer_low: 0,
+            gdt_selector: 0,
+            options: EntryOptions::minimal(),
+            pointer_middle: 0,
+            pointer_high: 0,
+            reserved: 0,
+            handler: PhantomData
+        }
+    }
+
+    fn set_handler_addr(&mut self, addr: u64) -> &mut EntryOptions {
+        self.pointer_low = addr as u16;
+        self.pointer_middle = (addr >> 16) as u16;
+        self.pointer_high = (addr >> 32) as u32;
+        self.gdt_selector = code_segment();
+
+        self.options.set_present(true);
+        &mut self.options
+    }
+}
+
+impl Entry<HandlerFunc> {
+    pub fn set_handler_fn(&mut self, handler: HandlerFunc) -> &mut EntryOptions {
+        self.set_handler_addr(handler as usize as u64)
+    }
+}
+
+impl Entry<HandlerFuncWithErrCode> {
+    pub fn set_handler_fn(&mut self, handler: HandlerFuncWithErrCode) -> &mut EntryOptions {
+        self.set_handler_addr(handler as usize as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EntryOptions(u16);
+
+impl EntryOptions {
+    // bits 9-11 have to be set for an interrupt gate
+    fn minimal() -> EntryOptions {
+        EntryOptions(0b1110_0000_0000)
+    }
+
+    fn set_present(&mut self, present: bool) -> &mut EntryOptions {
+        self.set_bit(15, present);
+        self
+    }
+
+    /// Interrupt gates disable interrupts on entry to the handler, trap gates don't.
+    pub fn disable_interrupts(&mut self, disable: bool) -> &mut EntryOptions {
+        self.set_bit(8, !disable);
+        self
+    }
+
+    pub fn set_privilege_level(&mut self, dpl: u16) -> &mut EntryOptions {
+        self.0 = (self.0 & !(0b11 << 13)) | ((dpl & 0b11) << 13);
+        self
+    }
+
+    /// Makes the CPU switch to the given Interrupt Stack Table stack before
+    /// calling the handler. Unsafe because the index has to be valid and the
+    /// stack must not be used by any other handler which can nest with this one.
+    pub unsafe fn set_stack_index(&mut self, index: u16) -> &mut EntryOptions {
+        // the hardware IST index starts at 1, 0 means no stack switch
+        self.0 = (self.0 & !0b111) | ((index + 1) & 0b111);
+        self
+    }
+
+    fn set_bit(&mut self, bit: u16, value: bool) {
+        if value {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
+        }
+    }
+}
+
+fn code_segment() -> u16 {
+    let segment: u16;
+    unsafe {
+        asm!("mov %cs, $0" : "=r" (segment));
+    }
+    segment
+}
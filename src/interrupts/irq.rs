@@ -0,0 +1,140 @@
+/*
+ *  Hardware IRQ dispatch.
+ *
+ *  Every IRQ line gets a fixed stub in the IDT which looks up the handler a
+ *  driver registered for that line, so drivers never touch the IDT directly.
+ *  Lines are masked and acknowledged through the 8259 PICs until the I/O
+ *  APIC takes over.
+ */
+
+use spin::Mutex;
+
+use interrupts::apic;
+use interrupts::idt::{Idt, HandlerFunc, ExceptionStackFrame};
+use interrupts::pic::{ChainedPics, PIC_1_OFFSET, PIC_2_OFFSET};
+use interrupts::without_interrupts;
+
+pub const IRQ_COUNT: usize = 16;
+
+/// Handler for a hardware interrupt, called with the IRQ line that fired.
+pub type IrqHandler = fn(u8);
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
+static HANDLERS: Mutex<[Option<IrqHandler>; IRQ_COUNT]> = Mutex::new([None; IRQ_COUNT]);
+
+macro_rules! irq_stubs {
+    ($($name:ident => $irq:expr),*) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: &mut ExceptionStackFrame) {
+                dispatch($irq);
+            }
+        )*
+
+        const STUBS: [HandlerFunc; IRQ_COUNT] = [$($name),*];
+    };
+}
+
+irq_stubs!(irq0 => 0, irq1 => 1, irq2 => 2, irq3 => 3, irq4 => 4, irq5 => 5, irq6 => 6, irq7 => 7,
+           irq8 => 8, irq9 => 9, irq10 => 10, irq11 => 11, irq12 => 12, irq13 => 13, irq14 => 14, irq15 => 15);
+
+/// Remaps the PICs, leaving every line masked until a handler is registered.
+pub fn init() {
+    unsafe { PICS.lock().initialize(); }
+}
+
+/// Hands the IRQ lines over to the I/O APIC, keeping the lines which have a
+/// handler unmasked, and masks the PICs. `apic::init` has to succeed first.
+pub fn switch_to_apic() {
+    without_interrupts(|| {
+        let handlers = HANDLERS.lock();
+        for (irq, handler) in handlers.iter().enumerate() {
+            if handler.is_some() {
+                apic::unmask(irq as u8);
+            }
+        }
+
+        unsafe { PICS.lock().disable(); }
+    });
+}
+
+/// Points the IDT vectors the PICs were remapped to at the dispatch stubs.
+pub fn set_handlers(idt: &mut Idt) {
+    for (irq, stub) in STUBS.iter().enumerate() {
+        idt.interrupts[vector(irq as u8) as usize - 32].set_handler_fn(*stub);
+    }
+}
+
+/// Attaches `handler` to an IRQ line and unmasks the line.
+pub fn register_handler(irq: u8, handler: IrqHandler) {
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        assert!(handlers[irq as usize].is_none(), "IRQ {} already has a handler", irq);
+        handlers[irq as usize] = Some(handler);
+
+        unmask_line(irq);
+    });
+}
+
+/// Masks an IRQ line and detaches its handler.
+pub fn unregister_handler(irq: u8) {
+    without_interrupts(|| {
+        mask_line(irq);
+        HANDLERS.lock()[irq as usize] = None;
+    });
+}
+
+pub fn mask(irq: u8) {
+    without_interrupts(|| mask_line(irq));
+}
+
+pub fn unmask(irq: u8) {
+    without_interrupts(|| unmask_line(irq));
+}
+
+fn mask_line(irq: u8) {
+    if apic::is_enabled() {
+        apic::mask(irq);
+    } else {
+        unsafe { PICS.lock().mask(irq); }
+    }
+}
+
+fn unmask_line(irq: u8) {
+    if apic::is_enabled() {
+        apic::unmask(irq);
+    } else {
+        unsafe { PICS.lock().unmask(irq); }
+    }
+}
+
+// the vector the PICs were remapped to, which the I/O APIC uses too
+fn vector(irq: u8) -> u8 {
+    if irq < 8 { PIC_1_OFFSET + irq } else { PIC_2_OFFSET + irq - 8 }
+}
+
+fn dispatch(irq: u8) {
+    let apic_mode = apic::is_enabled();
+    let spurious = if apic_mode {
+        // the masked PICs can still raise a spurious IRQ 7 or 15 on the same
+        // vectors, it never reaches the local APIC's in service register
+        (irq == 7 || irq == 15) && !apic::local_apic().map_or(false, |apic| apic.in_service(vector(irq)))
+    } else {
+        unsafe { PICS.lock().handle_spurious(irq) }
+    };
+    if spurious {
+        return;
+    }
+
+    // copy the handler out so the lock isn't held while it runs
+    let handler = HANDLERS.lock()[irq as usize];
+    match handler {
+        Some(handler) => handler(irq),
+        None => println!("unhandled IRQ {}", irq)
+    }
+
+    if apic_mode {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(irq); }
+    }
+}
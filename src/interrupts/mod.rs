@@ -0,0 +1,145 @@
+use spin::Once;
+
+use cmdline;
+use memory;
+
+pub use self::exceptions::hlt_loop;
+pub use self::idt::{Idt, ExceptionStackFrame};
+pub use self::irq::{IrqHandler, register_handler, unregister_handler, mask, unmask};
+pub use self::apic::{LocalApic, local_apic, set_timer_handler, TIMER_VECTOR as APIC_TIMER_VECTOR};
+
+use self::gdt::{Gdt, Descriptor, TaskStateSegment};
+
+mod apic;
+mod exceptions;
+mod gdt;
+mod idt;
+mod irq;
+mod pic;
+
+/// Interrupt Stack Table slots for exceptions which must not run on the
+/// interrupted stack.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_PAGES: usize = 2;
+
+static EARLY_IDT: Once<Idt> = Once::new();
+static IDT: Once<Idt> = Once::new();
+static TSS: Once<TaskStateSegment> = Once::new();
+static GDT: Once<Gdt> = Once::new();
+
+// operand of the lgdt and lidt instructions
+#[repr(C, packed)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64
+}
+
+/// Loads an IDT with handlers for every CPU exception, running on whatever
+/// stack was interrupted. Used until `init` can allocate the IST stacks.
+pub fn init_early() {
+    let idt = EARLY_IDT.call_once(|| {
+        let mut idt = Idt::new();
+        exceptions::set_handlers(&mut idt);
+        idt
+    });
+
+    idt.load();
+}
+
+/// Replaces the boot GDT with one containing a TSS, whose Interrupt Stack
+/// Table gives double faults, NMIs and machine checks their own stacks, remaps
+/// the PICs and loads the final IDT. Memory has to be initialized first.
+/// Interrupts stay disabled until `enable` is called.
+pub fn init() {
+    let tss = TSS.call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        for &index in [DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX].iter() {
+            let stack = memory::alloc_stack(IST_STACK_PAGES).expect("Could not allocate IST stack");
+            tss.interrupt_stack_table[index as usize] = stack.top() as u64;
+        }
+        tss
+    });
+
+    let mut code_selector = 0;
+    let mut data_selector = 0;
+    let mut tss_selector = 0;
+    let gdt = GDT.call_once(|| {
+        let mut gdt = Gdt::new();
+        code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+        gdt
+    });
+
+    gdt.load();
+    unsafe {
+        gdt::set_cs(code_selector);
+        gdt::load_data_segments(data_selector);
+        gdt::load_tss(tss_selector);
+    }
+
+    irq::init();
+
+    let idt = IDT.call_once(|| {
+        let mut idt = Idt::new();
+        exceptions::set_handlers(&mut idt);
+        exceptions::set_stack_indices(&mut idt);
+        irq::set_handlers(&mut idt);
+        apic::set_handlers(&mut idt);
+        idt
+    });
+
+    idt.load();
+}
+
+/// Switches IRQ delivery from the 8259 PICs to the local and I/O APICs if
+/// the ACPI tables describe them and `noapic` isn't on the command line.
+/// Returns false if the PICs stay in use. Needs `init` and `acpi::init` to
+/// have run.
+pub fn init_apic() -> bool {
+    if cmdline::config().noapic || !apic::init() {
+        return false;
+    }
+
+    irq::switch_to_apic();
+    true
+}
+
+/// Allows maskable interrupts to be delivered.
+pub fn enable() {
+    unsafe { asm!("sti" :::: "volatile"); }
+}
+
+pub fn disable() {
+    unsafe { asm!("cli" :::: "volatile"); }
+}
+
+pub fn are_enabled() -> bool {
+    let flags: u64;
+    unsafe { asm!("pushfq; popq $0" : "=r" (flags) ::: "volatile"); }
+    flags & (1 << 9) != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state afterwards.
+/// Anything that takes a lock also taken by an interrupt handler has to use this.
+pub fn without_interrupts<F, R>(f: F) -> R where F: FnOnce() -> R {
+    let were_enabled = are_enabled();
+    if were_enabled {
+        disable();
+    }
+
+    let result = f();
+
+    if were_enabled {
+        enable();
+    }
+    result
+}
+
+// temporary testing function
+pub fn test_breakpoint() {
+    unsafe { asm!("int3" :::: "volatile"); }
+}
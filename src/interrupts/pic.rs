@@ -0,0 +1,142 @@
+/*
+ *  Driver for the two cascaded legacy 8259 Programmable Interrupt Controllers.
+ *
+ *  Out of reset the PICs deliver IRQ 0-7 on vectors 8-15, right on top of the
+ *  CPU exceptions, so they're remapped to the vectors right after them.
+ */
+
+use x86::shared::io::{inb, outb};
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+// the slave PIC is cascaded into this line of the master
+const CASCADE_IRQ: u8 = 2;
+
+const CMD_INIT: u8 = 0x11;
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+const CMD_READ_ISR: u8 = 0x0B;
+const MODE_8086: u8 = 0x01;
+
+struct Pic {
+    offset: u8,
+    command_port: u16,
+    data_port: u16
+}
+
+impl Pic {
+    unsafe fn command(&self, command: u8) {
+        outb(self.command_port, command);
+    }
+
+    unsafe fn read_mask(&self) -> u8 {
+        inb(self.data_port)
+    }
+
+    unsafe fn write_mask(&self, mask: u8) {
+        outb(self.data_port, mask);
+    }
+
+    unsafe fn in_service(&self) -> u8 {
+        self.command(CMD_READ_ISR);
+        inb(self.command_port)
+    }
+}
+
+pub struct ChainedPics {
+    master: Pic,
+    slave: Pic
+}
+
+impl ChainedPics {
+    pub const fn new(offset1: u8, offset2: u8) -> ChainedPics {
+        ChainedPics {
+            master: Pic { offset: offset1, command_port: 0x20, data_port: 0x21 },
+            slave: Pic { offset: offset2, command_port: 0xA0, data_port: 0xA1 }
+        }
+    }
+
+    /// Remaps both PICs to their offsets and masks every line except the
+    /// cascade. Unsafe because it talks to the hardware directly.
+    pub unsafe fn initialize(&mut self) {
+        // start the initialization sequence, after which each PIC expects
+        // three more bytes on its data port
+        self.master.command(CMD_INIT);
+        io_wait();
+        self.slave.command(CMD_INIT);
+        io_wait();
+
+        // vector offsets
+        self.master.write_mask(self.master.offset);
+        io_wait();
+        self.slave.write_mask(self.slave.offset);
+        io_wait();
+
+        // tell the master there's a slave on the cascade line, and the slave its identity
+        self.master.write_mask(1 << CASCADE_IRQ);
+        io_wait();
+        self.slave.write_mask(CASCADE_IRQ);
+        io_wait();
+
+        self.master.write_mask(MODE_8086);
+        io_wait();
+        self.slave.write_mask(MODE_8086);
+        io_wait();
+
+        self.master.write_mask(!(1 << CASCADE_IRQ));
+        self.slave.write_mask(0xFF);
+    }
+
+    /// Masks every line of both PICs, used when the APIC takes over.
+    pub unsafe fn disable(&mut self) {
+        self.master.write_mask(0xFF);
+        self.slave.write_mask(0xFF);
+    }
+
+    pub unsafe fn mask(&mut self, irq: u8) {
+        let (pic, line) = self.pic_for(irq);
+        let mask = pic.read_mask();
+        pic.write_mask(mask | (1 << line));
+    }
+
+    pub unsafe fn unmask(&mut self, irq: u8) {
+        let (pic, line) = self.pic_for(irq);
+        let mask = pic.read_mask();
+        pic.write_mask(mask & !(1 << line));
+    }
+
+    pub unsafe fn notify_end_of_interrupt(&mut self, irq: u8) {
+        if irq >= 8 {
+            self.slave.command(CMD_END_OF_INTERRUPT);
+        }
+        self.master.command(CMD_END_OF_INTERRUPT);
+    }
+
+    /// Checks whether an interrupt on IRQ 7 or 15 is spurious, meaning the PIC
+    /// withdrew it before the CPU acknowledged it. Spurious interrupts must not
+    /// be acknowledged, except on the master's cascade line for the slave's.
+    pub unsafe fn handle_spurious(&mut self, irq: u8) -> bool {
+        match irq {
+            7 if self.master.in_service() & (1 << 7) == 0 => true,
+            15 if self.slave.in_service() & (1 << 7) == 0 => {
+                self.master.command(CMD_END_OF_INTERRUPT);
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn pic_for(&self, irq: u8) -> (&Pic, u8) {
+        assert!(irq < 16, "IRQ {} out of range", irq);
+        if irq < 8 {
+            (&self.master, irq)
+        } else {
+            (&self.slave, irq - 8)
+        }
+    }
+}
+
+// writing to an unused port gives the PICs time to react on old hardware
+unsafe fn io_wait() {
+    outb(0x80, 0);
+}
@@ -1,9 +1,12 @@
-#![feature(lang_items, const_fn, ptr_internals, core_intrinsics)]
+#![feature(lang_items, const_fn, ptr_internals, core_intrinsics, alloc, alloc_error_handler, asm, abi_x86_interrupt)]
 #![no_std]
 
 #[macro_use]
 extern crate bitflags;
 
+#[macro_use]
+extern crate alloc;
+
 extern crate rlibc;
 extern crate volatile;
 extern crate spin;
@@ -11,37 +14,121 @@ extern crate multiboot2;
 extern crate x86;
 
 #[macro_use]
+mod console;
+#[macro_use]
+mod log;
+mod cmdline;
+mod ansi;
+mod cp437;
+#[macro_use]
+mod serial;
 mod vga_buffer;
+mod acpi;
+mod font;
+mod framebuffer;
+mod interrupts;
+mod mb_tags;
 mod memory;
+mod time;
 
+use core::alloc::Layout;
 use core::intrinsics;
 use core::panic::PanicInfo;
 
-use memory::FrameAllocator;
+use memory::{FrameAllocator, FrameStats};
+
+// kernel timer interrupts per second
+const TICK_RATE: u32 = 1000;
 
 #[no_mangle]
 pub extern fn rust_main(mb_info_addr: usize) {
+    serial::init();
     vga_buffer::clear_screen();
     println!("Booted{}", "!");
 
+    // set up exception handlers first, so any fault from here on is reported
+    interrupts::init_early();
+
     let boot_info = unsafe {
         multiboot2::load(mb_info_addr)
     };
 
-    //print_memory_areas(boot_info);
+    let config = cmdline::init(boot_info);
+    log::init();
+    console::init();
+
+    // the BIOS areas searched for the RSDP may be handed out as free frames later
+    acpi::init_early(boot_info);
+
     //print_elf_sections(boot_info);
 
     let mut frame_allocator = get_frame_allocator(mb_info_addr, boot_info);
+    println!("{}", frame_allocator.stats());
+
+    // the frame bitmap has to stay reachable once we switch page tables
+    let bitmap_range = frame_allocator.bitmap_range();
+    let mut active_table = memory::remap_the_kernel(&mut frame_allocator, boot_info, &[bitmap_range]);
 
     //alloc_all_mem(frame_allocator);
-    memory::test_paging(&mut frame_allocator);
+    if config.run_test("paging") {
+        memory::test_paging(&mut active_table, &mut frame_allocator);
+    }
+
+    memory::init(active_table, frame_allocator);
+    if config.run_test("heap") {
+        memory::test_heap();
+    }
+
+    // without a VGA text buffer, text has to be drawn into the framebuffer,
+    // unless the command line chose the outputs
+    if framebuffer::init(boot_info) && config.console.is_none() {
+        console::set_outputs(console::FRAMEBUFFER | console::SERIAL);
+    }
+
+    println!("{}", memory::stats());
+    for cache in memory::slab_stats().iter() {
+        println!("    {}", cache);
+    }
+
+    // now that kernel stacks can be allocated, give the exceptions that
+    // can't trust the current stack their own
+    interrupts::init();
+    if config.run_test("breakpoint") {
+        interrupts::test_breakpoint();
+    }
+
+    if acpi::init() && interrupts::init_apic() {
+        println!("using APIC for interrupts");
+    } else {
+        println!("using 8259 PICs for interrupts");
+    }
+
+    time::init(TICK_RATE);
+    serial::enable_rx_interrupts();
+    interrupts::enable();
+
+    time::sleep_busy(core::time::Duration::from_millis(100));
+    println!("uptime after 100ms: {:?} ({} ticks)", time::uptime(), time::ticks());
 }
 
-fn get_frame_allocator(mb_info_addr: usize, boot_info: &multiboot2::BootInformation) -> memory::AreaFrameAllocator {
+fn get_frame_allocator(mb_info_addr: usize, boot_info: &multiboot2::BootInformation) -> memory::BitmapFrameAllocator {
     let memory_map_tag = boot_info.memory_map_tag().expect("Memory map tag required");
     let kernel_range = get_kernel_range(boot_info);
     let mb_range = get_mb_range(mb_info_addr, boot_info);
-    memory::AreaFrameAllocator::new(memory_map_tag.memory_areas(), kernel_range, mb_range)
+
+    // the boot modules are reserved too, so their frames aren't handed out
+    // before they have been read
+    let mut module_ranges = [(0, 0); memory::MAX_MODULES];
+    let mut module_count = 0;
+    for module in mb_tags::modules(boot_info).filter(|module| module.size() > 0) {
+        assert!(module_count < memory::MAX_MODULES, "At most {} boot modules are supported", memory::MAX_MODULES);
+        println!("module_start: 0x{:x}, module_end: 0x{:x}", module.start, module.end);
+        module_ranges[module_count] = (module.start, module.end - 1);
+        module_count += 1;
+    }
+
+    memory::BitmapFrameAllocator::new(memory_map_tag.memory_areas(), kernel_range, mb_range,
+                                      &module_ranges[..module_count], cmdline::config().memory_limit)
 }
 
 fn alloc_all_mem(frame_allocator: &mut FrameAllocator) {
@@ -53,15 +140,6 @@ fn alloc_all_mem(frame_allocator: &mut FrameAllocator) {
     }
 }
 
-fn print_memory_areas(boot_info: &multiboot2::BootInformation) {
-    let memory_map_tag = boot_info.memory_map_tag().expect("Memory map tag required");
-
-    println!("memory areas:");
-    for area in memory_map_tag.memory_areas() {
-        println!("    start: 0x{:x}, length: 0x{:x}", area.base_addr, area.length);
-    }
-}
-
 fn print_elf_sections(boot_info: &multiboot2::BootInformation) {
     let elf_sections_tag = boot_info.elf_sections_tag().expect("ELF sections tag required");
 
@@ -91,11 +169,26 @@ fn get_mb_range(mb_start: usize, boot_info: &multiboot2::BootInformation) -> (us
 #[lang = "eh_personality"] extern fn eh_personality() {}
 
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    if let Some(location) = _info.location() {
+fn panic(info: &PanicInfo) -> ! {
+    // the serial port comes first and ignores its lock, so the panic is
+    // visible on a headless machine even if it happened while printing
+    unsafe { serial::force_print(format_args!("\n\n{}\n", info)); }
+
+    if let Some(location) = info.location() {
         println!("\n\nPANIC in {} at line {}:", location.file(), location.line());
     }
 
+    // the messages leading up to the panic may have scrolled away by now
+    println!("kernel log:");
+    log::dump();
+
+    unsafe { intrinsics::abort() }
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    error!("failed to allocate {} bytes with alignment {}", layout.size(), layout.align());
+
     unsafe { intrinsics::abort() }
 }
 
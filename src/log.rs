@@ -0,0 +1,349 @@
+/*
+ *  Kernel log.
+ *
+ *  The error!, warn!, info!, debug! and trace! macros print a message with
+ *  its level, module and the wall-clock time to the console, and keep it in
+ *  a ring buffer so recent messages can be dumped later, like dmesg, which
+ *  the panic handler does. Messages logged before the RTC has been read are
+ *  stamped with the time since boot instead. Messages above the maximum level, or the level
+ *  set for their module, are dropped before they are formatted.
+ */
+
+use core::fmt::{self, Write};
+use core::str;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+
+use cmdline;
+use interrupts::without_interrupts;
+use time::{self, DateTime};
+
+// messages kept in the ring buffer
+const RECORD_COUNT: usize = 256;
+// longer messages are printed completely, but cut off in the ring buffer
+const MESSAGE_SIZE: usize = 120;
+/// Maximum number of modules with their own level.
+pub const MAX_FILTERS: usize = 8;
+
+const DEFAULT_LEVEL: Level = Level::Info;
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ({
+        let level = $level;
+        if $crate::log::enabled(level, module_path!()) {
+            $crate::log::log(level, module_path!(), format_args!($($arg)*));
+        }
+    });
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => (log!($crate::log::Level::Error, $($arg)*));
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => (log!($crate::log::Level::Warn, $($arg)*));
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => (log!($crate::log::Level::Info, $($arg)*));
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => (log!($crate::log::Level::Debug, $($arg)*));
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => (log!($crate::log::Level::Trace, $($arg)*));
+}
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn  = 2,
+    Info  = 3,
+    Debug = 4,
+    Trace = 5
+}
+
+impl Level {
+    /// Parses a lowercase level name, eg. `debug`.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn"  => Some(Level::Warn),
+            "info"  => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None
+        }
+    }
+
+    fn from_usize(level: usize) -> Level {
+        match level {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn  => "WARN",
+            Level::Info  => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE"
+        }
+    }
+
+    // ANSI color the level name is printed in
+    fn color(&self) -> &'static str {
+        match *self {
+            Level::Error => "\x1b[91m",
+            Level::Warn  => "\x1b[93m",
+            Level::Info  => "\x1b[92m",
+            Level::Debug => "\x1b[96m",
+            Level::Trace => "\x1b[37m"
+        }
+    }
+}
+
+/// Overrides the maximum level for a module and its submodules.
+#[derive(Debug, Clone, Copy)]
+struct Filter {
+    module: &'static str,
+    level: Level
+}
+
+impl Filter {
+    fn matches(&self, module: &str) -> bool {
+        module.starts_with(self.module) &&
+            (module.len() == self.module.len() || module[self.module.len()..].starts_with("::"))
+    }
+}
+
+// when a message was logged, in nanoseconds since the Unix epoch, or since
+// boot until the RTC has been read
+#[derive(Clone, Copy)]
+enum Timestamp {
+    Uptime(u64),
+    WallClock(u64)
+}
+
+impl Timestamp {
+    fn now() -> Timestamp {
+        match time::wall_clock() {
+            Some(wall_clock) => Timestamp::WallClock(as_nanos(wall_clock)),
+            None => Timestamp::Uptime(as_nanos(time::uptime()))
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Timestamp::Uptime(nanos) =>
+                write!(f, "{:5}.{:06}", nanos / 1_000_000_000, nanos % 1_000_000_000 / 1000),
+            Timestamp::WallClock(nanos) =>
+                write!(f, "{}.{:06}", DateTime::from_unix(nanos / 1_000_000_000), nanos % 1_000_000_000 / 1000)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    timestamp: Timestamp,
+    level: Level,
+    module: &'static str,
+    message: [u8; MESSAGE_SIZE],
+    len: usize
+}
+
+const EMPTY_RECORD: Record = Record {
+    timestamp: Timestamp::Uptime(0),
+    level: Level::Trace,
+    module: "",
+    message: [0; MESSAGE_SIZE],
+    len: 0
+};
+
+impl Record {
+    fn message(&self) -> &str {
+        str::from_utf8(&self.message[..self.len]).unwrap_or("")
+    }
+}
+
+// appends to the message, dropping whatever doesn't fit
+impl fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut utf8 = [0; 4];
+            let bytes = c.encode_utf8(&mut utf8).as_bytes();
+            if self.len + bytes.len() > MESSAGE_SIZE {
+                break;
+            }
+            self.message[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+/// The most recent messages, overwriting the oldest one once full.
+struct Ring {
+    records: [Record; RECORD_COUNT],
+    // number of messages ever logged, the next one goes to `total % RECORD_COUNT`
+    total: usize
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring { records: [EMPTY_RECORD; RECORD_COUNT], total: 0 }
+    }
+
+    fn push(&mut self, timestamp: Timestamp, level: Level, module: &'static str, args: fmt::Arguments) {
+        let record = &mut self.records[self.total % RECORD_COUNT];
+        *record = EMPTY_RECORD;
+        record.timestamp = timestamp;
+        record.level = level;
+        record.module = module;
+        record.write_fmt(args).unwrap();
+        self.total += 1;
+    }
+
+    // sequence number of the oldest message still in the buffer
+    fn first(&self) -> usize {
+        self.total.saturating_sub(RECORD_COUNT)
+    }
+}
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(DEFAULT_LEVEL as usize);
+// highest level of MAX_LEVEL and all filters, which rules out most disabled
+// messages without taking the filter lock
+static MAX_ENABLED: AtomicUsize = AtomicUsize::new(DEFAULT_LEVEL as usize);
+static FILTERS: Mutex<[Option<Filter>; MAX_FILTERS]> = Mutex::new([None; MAX_FILTERS]);
+static RING: Mutex<Ring> = Mutex::new(Ring::new());
+
+/// Applies the log levels given on the command line.
+pub fn init() {
+    let config = cmdline::config();
+    if let Some(level) = config.log_level {
+        set_max_level(level);
+    }
+    for &(module, level) in config.module_log_levels() {
+        set_module_level(module, level);
+    }
+}
+
+/// Sets the maximum level of messages which are logged, for all modules
+/// without their own level.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+    without_interrupts(|| update_max_enabled(&FILTERS.lock()));
+}
+
+pub fn max_level() -> Level {
+    Level::from_usize(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Sets the maximum level for a module and its submodules, given by its path
+/// without the crate name, eg. `memory::paging`. Returns false if there are
+/// too many module levels already.
+pub fn set_module_level(module: &'static str, level: Level) -> bool {
+    without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+
+        let slot = filters.iter().position(|filter| filter.map_or(false, |filter| filter.module == module))
+            .or_else(|| filters.iter().position(|filter| filter.is_none()));
+        match slot {
+            Some(index) => {
+                filters[index] = Some(Filter { module: module, level: level });
+                update_max_enabled(&filters);
+                true
+            }
+            None => false
+        }
+    })
+}
+
+/// Returns whether a message at `level` from the module at `module_path` is
+/// logged. Used by the logging macros.
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    if level as usize > MAX_ENABLED.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    let module = strip_crate_name(module_path);
+    without_interrupts(|| {
+        // the most specific filter wins
+        FILTERS.lock().iter()
+            .filter_map(|filter| filter.filter(|filter| filter.matches(module)))
+            .max_by_key(|filter| filter.module.len())
+            .map_or(level <= max_level(), |filter| level <= filter.level)
+    })
+}
+
+/// Records a message and prints it to the console. Use the logging macros
+/// instead, they skip disabled messages.
+pub fn log(level: Level, module_path: &'static str, args: fmt::Arguments) {
+    let timestamp = Timestamp::now();
+    let module = strip_crate_name(module_path);
+
+    without_interrupts(|| RING.lock().push(timestamp, level, module, args));
+    print_message(timestamp, level, module, args);
+}
+
+/// Prints the messages in the ring buffer, oldest first. Also called by the
+/// panic handler.
+pub fn dump() {
+    // the ring is only locked with interrupts disabled, so if it's locked
+    // here we panicked while logging and waiting for it would hang
+    let mut next = match without_interrupts(|| RING.try_lock().map(|ring| ring.first())) {
+        Some(first) => first,
+        None => return
+    };
+    loop {
+        // copy one message at a time, printing with the ring locked would
+        // hold up logging from interrupt handlers
+        let record = without_interrupts(|| {
+            let ring = RING.lock();
+            // skip messages which were overwritten in the meantime
+            next = next.max(ring.first());
+            if next < ring.total { Some(ring.records[next % RECORD_COUNT]) } else { None }
+        });
+
+        match record {
+            Some(record) => print_message(record.timestamp, record.level, record.module, format_args!("{}", record.message())),
+            None => break
+        }
+        next += 1;
+    }
+}
+
+fn print_message(timestamp: Timestamp, level: Level, module: &str, args: fmt::Arguments) {
+    println!("[{}] {}{:5}\x1b[0m {}: {}", timestamp, level.color(), level.name(), module, args);
+}
+
+fn as_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+// module paths start with the crate name, which is the same for every module
+fn strip_crate_name(module_path: &str) -> &str {
+    match module_path.find("::") {
+        Some(index) => &module_path[index + 2..],
+        None => module_path
+    }
+}
+
+fn update_max_enabled(filters: &[Option<Filter>; MAX_FILTERS]) {
+    let max = filters.iter()
+        .filter_map(|filter| filter.map(|filter| filter.level as usize))
+        .fold(MAX_LEVEL.load(Ordering::Relaxed), |max, level| max.max(level));
+    MAX_ENABLED.store(max, Ordering::Relaxed);
+}
@@ -0,0 +1,118 @@
+/*
+ *  Access to raw multiboot2 information tags.
+ *
+ *  The multiboot2 crate only knows about a handful of tag types, this walks
+ *  the tag list directly for the ones it doesn't parse (eg. ACPI, framebuffer).
+ */
+
+use core::{slice, str};
+
+use multiboot2::BootInformation;
+
+use memory::PhysicalAddress;
+
+pub const TAG_CMDLINE: u32 = 1;
+pub const TAG_MODULE: u32 = 3;
+pub const TAG_FRAMEBUFFER: u32 = 8;
+pub const TAG_ACPI_OLD_RSDP: u32 = 14;
+pub const TAG_ACPI_NEW_RSDP: u32 = 15;
+
+const TAG_END: u32 = 0;
+
+#[repr(C)]
+pub struct RawTag {
+    pub typ: u32,
+    pub size: u32
+}
+
+impl RawTag {
+    /// Address of the tag payload, directly after the type and size fields.
+    pub fn data_address(&self) -> usize {
+        self as *const _ as usize + 8
+    }
+
+    pub fn data_size(&self) -> usize {
+        self.size as usize - 8
+    }
+}
+
+/// Iterator over all tags, in the order the bootloader put them.
+pub struct Tags {
+    addr: usize,
+    end: usize
+}
+
+impl Iterator for Tags {
+    type Item = &'static RawTag;
+
+    fn next(&mut self) -> Option<&'static RawTag> {
+        if self.addr >= self.end {
+            return None;
+        }
+
+        let tag = unsafe { &*(self.addr as *const RawTag) };
+        if tag.typ == TAG_END {
+            self.addr = self.end;
+            return None;
+        }
+
+        // tags are padded to 8 byte alignment
+        self.addr += (tag.size as usize + 7) & !7;
+        Some(tag)
+    }
+}
+
+pub fn tags(boot_info: &BootInformation) -> Tags {
+    // tags start after the total_size and reserved fields
+    Tags { addr: boot_info.start_address() + 8, end: boot_info.end_address() }
+}
+
+/// Returns the first tag of the given type.
+pub fn find_tag(boot_info: &BootInformation, typ: u32) -> Option<&'static RawTag> {
+    tags(boot_info).find(|tag| tag.typ == typ)
+}
+
+/// A file the bootloader loaded into memory next to the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    pub start: PhysicalAddress,
+    pub end: PhysicalAddress,
+    /// The arguments given after the file name, eg. in GRUB's `module2` command.
+    pub cmdline: &'static str
+}
+
+impl Module {
+    fn from_tag(tag: &RawTag) -> Module {
+        let data = tag.data_address();
+        Module {
+            start: unsafe { *(data as *const u32) } as PhysicalAddress,
+            end: unsafe { *((data + 4) as *const u32) } as PhysicalAddress,
+            cmdline: unsafe { read_str(data + 8, tag.data_size() - 8) }
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// Iterates over the modules loaded by the bootloader.
+pub fn modules(boot_info: &BootInformation) -> impl Iterator<Item = Module> {
+    tags(boot_info).filter(|tag| tag.typ == TAG_MODULE).map(Module::from_tag)
+}
+
+/// The kernel command line, empty if the bootloader didn't pass one.
+pub fn command_line(boot_info: &BootInformation) -> &'static str {
+    match find_tag(boot_info, TAG_CMDLINE) {
+        Some(tag) => unsafe { read_str(tag.data_address(), tag.data_size()) },
+        None => ""
+    }
+}
+
+// reads a zero terminated string of at most `max_len` bytes, invalid UTF-8
+// reads as an empty string
+unsafe fn read_str(addr: usize, max_len: usize) -> &'static str {
+    let bytes = slice::from_raw_parts(addr as *const u8, max_len);
+    let len = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).unwrap_or("")
+}
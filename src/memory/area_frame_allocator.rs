@@ -1,4 +1,6 @@
-use memory::{Frame, FrameAllocator};
+use core::usize;
+
+use memory::{Frame, FrameAllocator, FrameStats, MemoryStats};
 use multiboot2::{MemoryAreaIter, MemoryArea};
 
 pub struct AreaFrameAllocator {
@@ -6,19 +8,26 @@ pub struct AreaFrameAllocator {
     current_area: Option<&'static MemoryArea>,
     areas: MemoryAreaIter,
     kernel_range: (Frame, Frame),
-    mb_range: (Frame, Frame)
+    mb_range: (Frame, Frame),
+    stats: MemoryStats
 }
 
 impl AreaFrameAllocator {
     pub fn new(memory_areas: MemoryAreaIter, kernel_range: (usize, usize), mb_range: (usize, usize)) -> AreaFrameAllocator {
         let (kernel_low_addr, kernel_high_addr) = kernel_range;
         let (mb_low_addr, mb_high_addr) = mb_range;
+        let kernel_range = (Frame::for_address(kernel_low_addr), Frame::for_address(kernel_high_addr));
+        let mb_range = (Frame::for_address(mb_low_addr), Frame::for_address(mb_high_addr));
+        let stats = MemoryStats::collect(memory_areas.clone(), &kernel_range, &[(mb_range.0 .0, mb_range.1 .0)],
+                                         None, usize::MAX);
+
         let mut allocator = AreaFrameAllocator {
             next_free_frame: Frame::for_address(0),
             current_area: None,
             areas: memory_areas,
-            kernel_range: (Frame::for_address(kernel_low_addr), Frame::for_address(kernel_high_addr)),
-            mb_range: (Frame::for_address(mb_low_addr), Frame::for_address(mb_high_addr))
+            kernel_range: kernel_range,
+            mb_range: mb_range,
+            stats: stats
         };
         allocator.choose_next_area();
         allocator
@@ -72,6 +81,7 @@ impl FrameAllocator for AreaFrameAllocator {
             } else {
                 // frame is not in any banned areas, allocate it
                 self.next_free_frame = Frame(next_free + 1);
+                self.stats.count_allocated(next_free);
                 Some(Frame(next_free))
             }
         } else {
@@ -83,3 +93,9 @@ impl FrameAllocator for AreaFrameAllocator {
         unimplemented!()
     }
 }
+
+impl FrameStats for AreaFrameAllocator {
+    fn stats(&self) -> MemoryStats {
+        self.stats
+    }
+}
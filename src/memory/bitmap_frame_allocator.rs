@@ -0,0 +1,207 @@
+use core::{cmp, slice, usize};
+
+use memory::{Frame, FrameAllocator, FrameStats, MemoryStats, PAGE_SIZE, IDENTITY_MAP_LIMIT, find_free_region};
+use multiboot2::MemoryAreaIter;
+
+const BITS_PER_WORD: usize = 64;
+
+/// Maximum number of boot modules whose memory can be reserved.
+pub const MAX_MODULES: usize = 8;
+
+// the kernel, the multiboot information and the bitmap, followed by the modules
+const MAX_RESERVED: usize = 3 + MAX_MODULES;
+
+/// Frame allocator which tracks every usable frame with a single bit.
+///
+/// A set bit means the frame is in use (or not backed by usable memory at all),
+/// a cleared bit means the frame is free. The bitmap itself lives in usable
+/// memory, in frames which are marked as used so they're never handed out.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    first_frame: usize,
+    frame_count: usize,
+    next_free_word: usize,
+    bitmap_range: (Frame, Frame),
+    // inclusive frame number ranges which must never be handed out or freed
+    reserved: [(usize, usize); MAX_RESERVED],
+    reserved_count: usize,
+    stats: MemoryStats
+}
+
+impl BitmapFrameAllocator {
+    /// Creates an allocator for the usable areas of the memory map, except
+    /// for the kernel, the multiboot information and the boot modules. With a
+    /// `memory_limit`, memory at and above that physical address is left unused.
+    pub fn new(memory_areas: MemoryAreaIter, kernel_range: (usize, usize), mb_range: (usize, usize),
+               module_ranges: &[(usize, usize)], memory_limit: Option<usize>) -> BitmapFrameAllocator {
+        assert!(module_ranges.len() <= MAX_MODULES, "At most {} boot modules are supported", MAX_MODULES);
+
+        let (kernel_low_addr, kernel_high_addr) = kernel_range;
+        let (mb_low_addr, mb_high_addr) = mb_range;
+        let kernel_range = (Frame::for_address(kernel_low_addr), Frame::for_address(kernel_high_addr));
+
+        // each module is reserved on its own, the memory between them stays usable
+        let mut reserved = [(0, 0); MAX_RESERVED];
+        reserved[0] = (kernel_range.0 .0, kernel_range.1 .0);
+        reserved[1] = (Frame::for_address(mb_low_addr).0, Frame::for_address(mb_high_addr).0);
+        for (i, &(low_addr, high_addr)) in module_ranges.iter().enumerate() {
+            reserved[i + 2] = (Frame::for_address(low_addr).0, Frame::for_address(high_addr).0);
+        }
+        let mut reserved_count = 2 + module_ranges.len();
+
+        let first_frame = memory_areas.clone()
+            .map(|area| Frame::for_address(area.base_addr as usize).0)
+            .min()
+            .expect("No usable memory areas");
+        let last_frame = memory_areas.clone()
+            .map(|area| Frame::for_address((area.base_addr + area.length - 1) as usize).0)
+            .max()
+            .unwrap();
+
+        // the command line rejects limits which are too small to boot with
+        let frame_limit = memory_limit.map_or(usize::MAX, |limit| limit / PAGE_SIZE);
+        let last_frame = cmp::min(last_frame, frame_limit - 1);
+
+        let frame_count = last_frame - first_frame + 1;
+        let word_count = (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let bitmap_size = word_count * (BITS_PER_WORD / 8);
+
+        // the bitmap has to be identity mapped and in memory we're allowed to use
+        let region_limit = memory_limit.map_or(IDENTITY_MAP_LIMIT, |limit| cmp::min(limit, IDENTITY_MAP_LIMIT));
+        let bitmap_addr = find_free_region(memory_areas.clone(), bitmap_size, &reserved[..reserved_count], region_limit)
+            .expect("No room for the frame bitmap");
+
+        let bitmap_range = (Frame::for_address(bitmap_addr), Frame::for_address(bitmap_addr + bitmap_size - 1));
+        let bitmap = unsafe { slice::from_raw_parts_mut(bitmap_addr as *mut u64, word_count) };
+
+        let stats = MemoryStats::collect(memory_areas.clone(), &kernel_range, &reserved[1..reserved_count],
+                                         Some(&bitmap_range), frame_limit);
+
+        reserved[reserved_count] = (bitmap_range.0 .0, bitmap_range.1 .0);
+        reserved_count += 1;
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap: bitmap,
+            first_frame: first_frame,
+            frame_count: frame_count,
+            next_free_word: 0,
+            bitmap_range: bitmap_range,
+            reserved: reserved,
+            reserved_count: reserved_count,
+            stats: stats
+        };
+
+        // everything starts out used, including the padding bits past the last
+        // frame, then only the frames fully contained in a usable area are freed
+        for word in allocator.bitmap.iter_mut() {
+            *word = !0;
+        }
+
+        for area in memory_areas {
+            let start = (area.base_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+            let end = cmp::min((area.base_addr + area.length) as usize / PAGE_SIZE, frame_limit);
+            if start < end {
+                allocator.mark_range(start, end - 1, false);
+            }
+        }
+
+        for &(start, end) in reserved[..reserved_count].iter() {
+            allocator.mark_range(start, end, true);
+        }
+
+        allocator
+    }
+
+    /// Returns the physical address range occupied by the bitmap itself, so it
+    /// can be kept mapped once the kernel switches to its own page tables.
+    pub fn bitmap_range(&self) -> (usize, usize) {
+        let (ref start, ref end) = self.bitmap_range;
+        (start.start_address(), end.start_address() + PAGE_SIZE - 1)
+    }
+
+    // marks the inclusive frame range [start, end] as used or free, ignoring
+    // any frames outside of the range covered by the bitmap
+    fn mark_range(&mut self, start: usize, end: usize, used: bool) {
+        let first = self.first_frame;
+        let last = self.first_frame + self.frame_count - 1;
+        if end < first || start > last {
+            return;
+        }
+
+        let start = if start < first { first } else { start };
+        let end = if end > last { last } else { end };
+        for frame_num in start..end + 1 {
+            self.set_used(frame_num, used);
+        }
+    }
+
+    fn bit_position(&self, frame_num: usize) -> (usize, u64) {
+        let bit = frame_num - self.first_frame;
+        (bit / BITS_PER_WORD, 1 << (bit % BITS_PER_WORD))
+    }
+
+    fn is_used(&self, frame_num: usize) -> bool {
+        let (word, mask) = self.bit_position(frame_num);
+        self.bitmap[word] & mask != 0
+    }
+
+    fn set_used(&mut self, frame_num: usize, used: bool) {
+        let (word, mask) = self.bit_position(frame_num);
+        if used {
+            self.bitmap[word] |= mask;
+        } else {
+            self.bitmap[word] &= !mask;
+        }
+    }
+
+    fn is_reserved(&self, frame_num: usize) -> bool {
+        self.reserved[..self.reserved_count].iter()
+            .any(|&(start, end)| start <= frame_num && frame_num <= end)
+    }
+}
+
+impl FrameAllocator for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        let word_count = self.bitmap.len();
+
+        // start searching where the last free frame was found, wrapping around
+        // once so frames freed below the hint are found again
+        for i in 0..word_count {
+            let word = (self.next_free_word + i) % word_count;
+            let bits = self.bitmap[word];
+            if bits != !0 {
+                let bit = (!bits).trailing_zeros() as usize;
+                self.bitmap[word] |= 1 << bit;
+                self.next_free_word = word;
+
+                let frame_num = self.first_frame + word * BITS_PER_WORD + bit;
+                self.stats.count_allocated(frame_num);
+                return Some(Frame(frame_num));
+            }
+        }
+
+        None
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        let Frame(frame_num) = frame;
+        assert!(self.first_frame <= frame_num && frame_num < self.first_frame + self.frame_count,
+            "Frame {} is not managed by this allocator", frame_num);
+        assert!(!self.is_reserved(frame_num), "Frame {} is reserved and cannot be freed", frame_num);
+        assert!(self.is_used(frame_num), "Frame {} freed twice", frame_num);
+
+        self.set_used(frame_num, false);
+        self.stats.count_freed(frame_num);
+
+        let (word, _) = self.bit_position(frame_num);
+        if word < self.next_free_word {
+            self.next_free_word = word;
+        }
+    }
+}
+
+impl FrameStats for BitmapFrameAllocator {
+    fn stats(&self) -> MemoryStats {
+        self.stats
+    }
+}
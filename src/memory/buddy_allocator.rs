@@ -0,0 +1,231 @@
+use core::{slice, usize};
+
+use memory::{Frame, FrameAllocator, FrameStats, MemoryStats, PAGE_SIZE, IDENTITY_MAP_LIMIT, find_free_region};
+use multiboot2::MemoryAreaIter;
+
+/// Largest supported block order, a block of order `n` spans `2^n` frames.
+/// Order 9 is a 2 MiB block, order 10 is a 4 MiB block.
+pub const MAX_ORDER: usize = 10;
+
+const ORDER_COUNT: usize = MAX_ORDER + 1;
+const BITS_PER_WORD: usize = 64;
+
+/// Buddy allocator for physically contiguous, naturally aligned runs of frames.
+///
+/// Free blocks are tracked with one bitmap per order, where a set bit means the
+/// block is free at exactly that order. Keeping the free lists out of the free
+/// memory itself means the allocator never has to touch memory it hands out,
+/// which matters once the kernel no longer identity maps all of physical memory.
+pub struct BuddyAllocator {
+    free_maps: &'static mut [u64],
+    order_offsets: [usize; ORDER_COUNT],
+    free_blocks: [usize; ORDER_COUNT],
+    frame_count: usize,
+    kernel_range: (Frame, Frame),
+    mb_range: (Frame, Frame),
+    map_range: (Frame, Frame),
+    stats: MemoryStats
+}
+
+impl BuddyAllocator {
+    pub fn new(memory_areas: MemoryAreaIter, kernel_range: (usize, usize), mb_range: (usize, usize)) -> BuddyAllocator {
+        let (kernel_low_addr, kernel_high_addr) = kernel_range;
+        let (mb_low_addr, mb_high_addr) = mb_range;
+        let kernel_range = (Frame::for_address(kernel_low_addr), Frame::for_address(kernel_high_addr));
+        let mb_range = (Frame::for_address(mb_low_addr), Frame::for_address(mb_high_addr));
+
+        // blocks are indexed from physical frame 0 so that block alignment
+        // matches physical address alignment
+        let frame_count = memory_areas.clone()
+            .map(|area| Frame::for_address((area.base_addr + area.length - 1) as usize).0 + 1)
+            .max()
+            .expect("No usable memory areas");
+
+        let mut order_offsets = [0; ORDER_COUNT];
+        let mut word_count = 0;
+        for order in 0..ORDER_COUNT {
+            let blocks = (frame_count + (1 << order) - 1) >> order;
+            order_offsets[order] = word_count;
+            word_count += (blocks + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        }
+        let map_size = word_count * (BITS_PER_WORD / 8);
+
+        let reserved = [(kernel_range.0 .0, kernel_range.1 .0), (mb_range.0 .0, mb_range.1 .0)];
+        let map_addr = find_free_region(memory_areas.clone(), map_size, &reserved, IDENTITY_MAP_LIMIT)
+            .expect("No room for the buddy allocator bitmaps");
+
+        let map_range = (Frame::for_address(map_addr), Frame::for_address(map_addr + map_size - 1));
+        let free_maps = unsafe { slice::from_raw_parts_mut(map_addr as *mut u64, word_count) };
+        for word in free_maps.iter_mut() {
+            *word = 0;
+        }
+
+        let stats = MemoryStats::collect(memory_areas.clone(), &kernel_range, &reserved[1..], Some(&map_range),
+                                         usize::MAX);
+
+        let mut allocator = BuddyAllocator {
+            free_maps: free_maps,
+            order_offsets: order_offsets,
+            free_blocks: [0; ORDER_COUNT],
+            frame_count: frame_count,
+            kernel_range: kernel_range,
+            mb_range: mb_range,
+            map_range: map_range,
+            stats: stats
+        };
+
+        for area in memory_areas {
+            let start = (area.base_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+            let end = (area.base_addr + area.length) as usize / PAGE_SIZE;
+            allocator.add_frames(start, end);
+        }
+
+        allocator
+    }
+
+    /// Allocates `2^order` physically contiguous frames, aligned to their size.
+    /// Returns the first frame of the block.
+    pub fn allocate_frames(&mut self, order: usize) -> Option<Frame> {
+        assert!(order <= MAX_ORDER, "Order {} is larger than the maximum order {}", order, MAX_ORDER);
+
+        // find the smallest free block which is large enough
+        let mut current = (order..ORDER_COUNT).find(|&o| self.free_blocks[o] > 0)?;
+        let mut block = self.take_free_block(current).unwrap();
+
+        // split it in halves until it has the requested size, freeing the upper buddies
+        while current > order {
+            current -= 1;
+            block <<= 1;
+            self.set_free(current, block + 1, true);
+        }
+
+        for frame_num in block << order..(block + 1) << order {
+            self.stats.count_allocated(frame_num);
+        }
+        Some(Frame(block << order))
+    }
+
+    /// Returns a block previously obtained from `allocate_frames` with the same order,
+    /// merging it with its buddy as long as the buddy is free.
+    pub fn deallocate_frames(&mut self, frame: Frame, order: usize) {
+        let Frame(frame_num) = frame;
+        assert!(order <= MAX_ORDER, "Order {} is larger than the maximum order {}", order, MAX_ORDER);
+        assert!(frame_num % (1 << order) == 0, "Frame {} is not aligned to order {}", frame_num, order);
+        assert!(frame_num + (1 << order) <= self.frame_count, "Frame {} is not managed by this allocator", frame_num);
+        assert!(!self.overlaps_reserved(frame_num, frame_num + (1 << order) - 1),
+            "Frame {} is reserved and cannot be freed", frame_num);
+        assert!(!self.is_free(order, frame_num >> order), "Frame {} freed twice", frame_num);
+
+        self.free_block(frame_num >> order, order);
+        for frame_num in frame_num..frame_num + (1 << order) {
+            self.stats.count_freed(frame_num);
+        }
+    }
+
+    /// Number of free blocks of each order.
+    pub fn free_blocks(&self) -> &[usize; ORDER_COUNT] {
+        &self.free_blocks
+    }
+
+    // frees every usable frame in [start, end) as blocks which are as large as
+    // alignment and the reserved ranges allow
+    fn add_frames(&mut self, start: usize, end: usize) {
+        let mut frame_num = start;
+        while frame_num < end {
+            if self.overlaps_reserved(frame_num, frame_num) {
+                frame_num += 1;
+                continue;
+            }
+
+            let mut order = MAX_ORDER;
+            while order > 0 && (frame_num % (1 << order) != 0
+                                || frame_num + (1 << order) > end
+                                || self.overlaps_reserved(frame_num, frame_num + (1 << order) - 1)) {
+                order -= 1;
+            }
+
+            self.free_block(frame_num >> order, order);
+            frame_num += 1 << order;
+        }
+    }
+
+    fn free_block(&mut self, mut block: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = block ^ 1;
+            if !self.is_free(order, buddy) {
+                break;
+            }
+
+            // buddy is free too, so merge both into a block of the next order
+            self.set_free(order, buddy, false);
+            block >>= 1;
+            order += 1;
+        }
+
+        self.set_free(order, block, true);
+    }
+
+    fn take_free_block(&mut self, order: usize) -> Option<usize> {
+        let start = self.order_offsets[order];
+        let end = if order + 1 < ORDER_COUNT { self.order_offsets[order + 1] } else { self.free_maps.len() };
+
+        for word in start..end {
+            let bits = self.free_maps[word];
+            if bits != 0 {
+                let block = (word - start) * BITS_PER_WORD + bits.trailing_zeros() as usize;
+                self.set_free(order, block, false);
+                return Some(block);
+            }
+        }
+
+        None
+    }
+
+    fn bit_position(&self, order: usize, block: usize) -> Option<(usize, u64)> {
+        if block << order >= self.frame_count {
+            return None;
+        }
+        Some((self.order_offsets[order] + block / BITS_PER_WORD, 1 << (block % BITS_PER_WORD)))
+    }
+
+    fn is_free(&self, order: usize, block: usize) -> bool {
+        match self.bit_position(order, block) {
+            Some((word, mask)) => self.free_maps[word] & mask != 0,
+            None => false
+        }
+    }
+
+    fn set_free(&mut self, order: usize, block: usize, free: bool) {
+        let (word, mask) = self.bit_position(order, block).expect("Block out of range");
+        if free {
+            self.free_maps[word] |= mask;
+            self.free_blocks[order] += 1;
+        } else {
+            self.free_maps[word] &= !mask;
+            self.free_blocks[order] -= 1;
+        }
+    }
+
+    // checks whether the inclusive frame range [start, end] overlaps any frame
+    // that must never be handed out
+    fn overlaps_reserved(&self, start: usize, end: usize) -> bool {
+        [&self.kernel_range, &self.mb_range, &self.map_range].iter()
+            .any(|&&(Frame(low), Frame(high))| start <= high && low <= end)
+    }
+}
+
+impl FrameAllocator for BuddyAllocator {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        self.allocate_frames(0)
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.deallocate_frames(frame, 0)
+    }
+}
+
+impl FrameStats for BuddyAllocator {
+    fn stats(&self) -> MemoryStats {
+        self.stats
+    }
+}
@@ -0,0 +1,182 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::ptr;
+
+use spin::Mutex;
+
+use interrupts::without_interrupts;
+use memory::slab_allocator::{SlabAllocator, PageSource, CacheStats, CACHE_COUNT};
+
+/// Header of a free heap region, stored at the start of the region itself.
+struct ListNode {
+    size: usize,
+    next: *mut ListNode
+}
+
+const NODE_SIZE: usize = mem::size_of::<ListNode>();
+const NODE_ALIGN: usize = mem::align_of::<ListNode>();
+
+/// First-fit allocator which keeps the free regions of the heap in a linked
+/// list sorted by address, so freed regions can be merged with their neighbours.
+pub struct LinkedListAllocator {
+    // dummy node which is never handed out, its `next` is the first free region
+    head: ListNode
+}
+
+// the raw pointers only ever point into the heap, which is owned by the allocator
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn empty() -> LinkedListAllocator {
+        LinkedListAllocator {
+            head: ListNode { size: 0, next: ptr::null_mut() }
+        }
+    }
+
+    /// Hands the memory range `[heap_start, heap_start + heap_size)` to the
+    /// allocator. Unsafe because the range has to be mapped and unused.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<usize> {
+        let (size, align) = LinkedListAllocator::size_align(layout);
+
+        unsafe {
+            let mut prev: *mut ListNode = &mut self.head;
+            let mut current = self.head.next;
+
+            while !current.is_null() {
+                let region_start = current as usize;
+                let region_end = region_start + (*current).size;
+
+                // any padding in front of the allocation has to be able to
+                // hold a node of its own, otherwise it would be lost
+                let mut alloc_start = align_up(region_start, align);
+                if alloc_start != region_start && alloc_start - region_start < NODE_SIZE {
+                    alloc_start = align_up(region_start + NODE_SIZE, align);
+                }
+                let alloc_end = alloc_start + size;
+
+                let back_padding = region_end.saturating_sub(alloc_end);
+                if alloc_end <= region_end && (back_padding == 0 || back_padding >= NODE_SIZE) {
+                    // region fits, take it out of the list and give back what we don't need
+                    (*prev).next = (*current).next;
+
+                    if alloc_start > region_start {
+                        self.add_free_region(region_start, alloc_start - region_start);
+                    }
+                    if back_padding > 0 {
+                        self.add_free_region(alloc_end, back_padding);
+                    }
+
+                    return Some(alloc_start);
+                }
+
+                prev = current;
+                current = (*current).next;
+            }
+        }
+
+        None
+    }
+
+    pub unsafe fn deallocate(&mut self, addr: usize, layout: Layout) {
+        let (size, _) = LinkedListAllocator::size_align(layout);
+        self.add_free_region(addr, size);
+    }
+
+    // every allocation has to be able to hold a node once it's freed again
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let size = align_up(if layout.size() < NODE_SIZE { NODE_SIZE } else { layout.size() }, NODE_ALIGN);
+        let align = if layout.align() < NODE_ALIGN { NODE_ALIGN } else { layout.align() };
+        (size, align)
+    }
+
+    // inserts the region into the sorted free list, merging it with the
+    // regions directly before and after it if they're adjacent
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        assert!(align_up(addr, NODE_ALIGN) == addr);
+        assert!(size >= NODE_SIZE);
+
+        let head: *mut ListNode = &mut self.head;
+        let mut prev = head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < addr {
+            prev = (*prev).next;
+        }
+
+        let mut size = size;
+        let mut next = (*prev).next;
+        if !next.is_null() && addr + size == next as usize {
+            size += (*next).size;
+            next = (*next).next;
+        }
+
+        if prev != head && prev as usize + (*prev).size == addr {
+            (*prev).size += size;
+            (*prev).next = next;
+        } else {
+            let node = addr as *mut ListNode;
+            ptr::write(node, ListNode { size: size, next: next });
+            (*prev).next = node;
+        }
+    }
+}
+
+/// The kernel heap behind a spinlock, so it can be used as the global allocator.
+///
+/// Small allocations are served by slab caches, everything else comes from
+/// a `LinkedListAllocator`.
+pub struct LockedHeap(Mutex<SlabAllocator>);
+
+impl LockedHeap {
+    pub const fn empty() -> LockedHeap {
+        LockedHeap(Mutex::new(SlabAllocator::empty()))
+    }
+
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize, page_source: PageSource) {
+        self.0.lock().init(heap_start, heap_size, page_source);
+    }
+
+    pub fn cache_stats(&self) -> [CacheStats; CACHE_COUNT] {
+        without_interrupts(|| self.0.lock().cache_stats())
+    }
+}
+
+// interrupt handlers may allocate and free too, eg. when running timer
+// callbacks, so the lock must never be held while one can run
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| {
+            self.0.lock().allocate(layout).map_or(ptr::null_mut(), |addr| addr as *mut u8)
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.lock().deallocate(ptr as usize, layout))
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "Alignment must be a power of two");
+    (addr + align - 1) & !(align - 1)
+}
+
+pub fn test_heap() {
+    use alloc::boxed::Box;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    let boxed = Box::new(42);
+    println!("Box on the heap at {:p}: {}", boxed, boxed);
+
+    let mut vec = Vec::new();
+    for i in 0..500 {
+        vec.push(i);
+    }
+    println!("Vec of {} elements at {:p}, sum: {}", vec.len(), vec.as_ptr(), vec.iter().sum::<u64>());
+
+    let mut string = String::from("Heap");
+    string.push_str(" works!");
+    println!("{}", string);
+}
@@ -1,12 +1,36 @@
 pub use self::area_frame_allocator::AreaFrameAllocator;
+pub use self::bitmap_frame_allocator::{BitmapFrameAllocator, MAX_MODULES};
+pub use self::buddy_allocator::BuddyAllocator;
+pub use self::stats::{MemoryStats, AreaStats};
+pub use self::stack_allocator::{StackAllocator, Stack};
+pub use self::heap_allocator::LockedHeap;
+pub use self::slab_allocator::CacheStats;
 
-// temporary testing function
+pub use self::paging::{ActivePageTable, remap_the_kernel};
+pub use self::paging::{PhysicalAddress, VirtualAddress, EntryFlags};
+pub use self::paging::{PRESENT, WRITABLE, WRITE_THROUGH, NO_CACHE, NO_EXECUTE};
+
+// temporary testing functions
 pub use self::paging::test_paging;
+pub use self::heap_allocator::test_heap;
+
+use core::cmp;
 
-use self::paging::PhysicalAddress;
+use self::paging::{Page, PageIter};
+use self::slab_allocator::CACHE_COUNT;
+use self::stack_allocator::{KERNEL_STACKS_START, KERNEL_STACKS_SIZE, boot_stack_guard_page};
+use interrupts::without_interrupts;
+use multiboot2::MemoryAreaIter;
+use spin::Mutex;
 
 mod area_frame_allocator;
+mod bitmap_frame_allocator;
+mod buddy_allocator;
+mod heap_allocator;
 mod paging;
+mod slab_allocator;
+mod stack_allocator;
+mod stats;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Frame(usize);
@@ -21,9 +45,217 @@ impl Frame {
     fn start_address(&self) -> PhysicalAddress {
         self.0 * PAGE_SIZE
     }
+
+    // frames deliberately don't implement Clone, since handing out a copy of
+    // a frame usually means it's about to be freed or mapped twice
+    fn clone(&self) -> Frame {
+        Frame(self.0)
+    }
+
+    fn range_inclusive(start: Frame, end: Frame) -> FrameIter {
+        FrameIter {
+            start: start,
+            end: end
+        }
+    }
+}
+
+struct FrameIter {
+    start: Frame,
+    end: Frame
+}
+
+impl Iterator for FrameIter {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.start <= self.end {
+            let frame = self.start.clone();
+            self.start.0 += 1;
+            Some(frame)
+        } else {
+            None
+        }
+    }
 }
 
 pub trait FrameAllocator {
     fn allocate_frame(&mut self) -> Option<Frame>;
     fn deallocate_frame(&mut self, frame: Frame);
 }
+
+/// Implemented by the frame allocators which can report physical memory usage.
+pub trait FrameStats {
+    fn stats(&self) -> MemoryStats;
+}
+
+// finds the lowest page aligned address of `size` bytes inside a usable memory
+// area which doesn't overlap any of the reserved inclusive frame number ranges
+// and ends below `limit`, used to place allocator bookkeeping in physical
+// memory before any allocator exists
+fn find_free_region(memory_areas: MemoryAreaIter, size: usize, reserved: &[(usize, usize)],
+                    limit: usize) -> Option<usize> {
+    memory_areas.filter_map(|area| {
+        let area_end = cmp::min((area.base_addr + area.length) as usize, limit);
+        let mut start = (area.base_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+        // never place anything in the null page, references to it are invalid
+        if start == 0 {
+            start = PAGE_SIZE;
+        }
+
+        while start + size <= area_end {
+            let start_frame = Frame::for_address(start).0;
+            let end_frame = Frame::for_address(start + size - 1).0;
+
+            let overlap = reserved.iter()
+                .filter(|&&(low, high)| start_frame <= high && low <= end_frame)
+                .map(|&(_, high)| high)
+                .max();
+
+            match overlap {
+                // skip past the reserved range and try again
+                Some(high) => start = (high + 1) * PAGE_SIZE,
+                None => return Some(start)
+            }
+        }
+
+        None
+    }).min()
+}
+
+// the boot page tables only identity map the first 1 GiB of physical memory,
+// so allocator bookkeeping has to live below this address to be accessible
+const IDENTITY_MAP_LIMIT: usize = 1 << 30;
+
+/// Virtual address range backing the kernel heap.
+pub const HEAP_START: usize = 0x0000_4444_4444_0000;
+pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
+
+#[global_allocator]
+static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+/// Virtual address range slab caches map their pages into.
+const SLAB_AREA_START: usize = 0x0000_5555_0000_0000;
+const SLAB_AREA_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+/// Owns the kernel's page tables and allocators once memory is set up.
+pub struct MemoryController {
+    active_table: ActivePageTable,
+    frame_allocator: BitmapFrameAllocator,
+    stack_allocator: StackAllocator,
+    slab_pages: PageIter
+}
+
+// the controller is only ever reached through MEMORY_CONTROLLER's lock
+unsafe impl Send for MemoryController {}
+
+// the heap takes this lock to map slab pages, and interrupt handlers may
+// allocate, so it must never be held while one can run
+static MEMORY_CONTROLLER: Mutex<Option<MemoryController>> = Mutex::new(None);
+
+impl MemoryController {
+    fn alloc_stack(&mut self, size_in_pages: usize) -> Option<Stack> {
+        let &mut MemoryController { ref mut active_table, ref mut frame_allocator, ref mut stack_allocator, .. } = self;
+        stack_allocator.alloc_stack(active_table, frame_allocator, size_in_pages)
+    }
+
+    fn identity_map(&mut self, start: PhysicalAddress, size: usize, flags: EntryFlags) {
+        let start_frame = Frame::for_address(start);
+        let end_frame = Frame::for_address(start + size - 1);
+        for frame in Frame::range_inclusive(start_frame, end_frame) {
+            if self.active_table.translate(frame.start_address()).is_none() {
+                self.active_table.identity_map(frame, flags, &mut self.frame_allocator);
+            }
+        }
+    }
+
+    fn alloc_slab_page(&mut self) -> Option<VirtualAddress> {
+        let page = self.slab_pages.next()?;
+        self.active_table.map(page, paging::WRITABLE | paging::NO_EXECUTE, &mut self.frame_allocator);
+        Some(page.start_address())
+    }
+}
+
+/// Maps the kernel heap and hands it to the global allocator, after which the
+/// `alloc` crate can be used. Must be called once, after the kernel has been remapped.
+pub fn init(mut active_table: ActivePageTable, mut frame_allocator: BitmapFrameAllocator) {
+    let heap_start_page = Page::for_address(HEAP_START);
+    let heap_end_page = Page::for_address(HEAP_START + HEAP_SIZE - 1);
+    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
+        active_table.map(page, paging::WRITABLE | paging::NO_EXECUTE, &mut frame_allocator);
+    }
+
+    let stack_allocator = {
+        let stack_start_page = Page::for_address(KERNEL_STACKS_START);
+        let stack_end_page = Page::for_address(KERNEL_STACKS_START + KERNEL_STACKS_SIZE - 1);
+        StackAllocator::new(Page::range_inclusive(stack_start_page, stack_end_page))
+    };
+
+    let slab_pages = Page::range_inclusive(Page::for_address(SLAB_AREA_START),
+                                           Page::for_address(SLAB_AREA_START + SLAB_AREA_SIZE - 1));
+
+    let controller = MemoryController {
+        active_table: active_table,
+        frame_allocator: frame_allocator,
+        stack_allocator: stack_allocator,
+        slab_pages: slab_pages
+    };
+    without_interrupts(|| *MEMORY_CONTROLLER.lock() = Some(controller));
+
+    unsafe {
+        HEAP_ALLOCATOR.init(HEAP_START, HEAP_SIZE, alloc_slab_page);
+    }
+}
+
+/// Allocates a kernel stack of `size_in_pages` pages with a guard page below it.
+pub fn alloc_stack(size_in_pages: usize) -> Option<Stack> {
+    without_interrupts(|| {
+        MEMORY_CONTROLLER.lock().as_mut().expect("Memory not initialized").alloc_stack(size_in_pages)
+    })
+}
+
+/// Checks whether an access to `address` hit the guard page below the boot
+/// stack or below a stack handed out by `alloc_stack`.
+pub fn is_stack_guard(address: VirtualAddress) -> bool {
+    let boot_guard = boot_stack_guard_page().start_address();
+    if boot_guard <= address && address < boot_guard + PAGE_SIZE {
+        return true;
+    }
+
+    // this runs in the page fault handler, which may have interrupted the
+    // lock holder, so kernel stack guards are only recognized if it's free
+    MEMORY_CONTROLLER.try_lock().map_or(false, |controller| {
+        controller.as_ref().map_or(false, |controller| controller.stack_allocator.is_guard_page(address))
+    })
+}
+
+/// Identity maps the physical range `[start, start + size)` for memory which
+/// isn't managed by the frame allocator, like MMIO registers or firmware
+/// tables. Pages which are already mapped are left as they are.
+pub fn identity_map(start: PhysicalAddress, size: usize, flags: EntryFlags) {
+    without_interrupts(|| {
+        MEMORY_CONTROLLER.lock().as_mut().expect("Memory not initialized").identity_map(start, size, flags)
+    })
+}
+
+/// Physical memory statistics of the kernel frame allocator.
+pub fn stats() -> MemoryStats {
+    without_interrupts(|| {
+        MEMORY_CONTROLLER.lock().as_ref().expect("Memory not initialized").frame_allocator.stats()
+    })
+}
+
+/// Usage counters of every slab cache of the kernel heap.
+pub fn slab_stats() -> [CacheStats; CACHE_COUNT] {
+    HEAP_ALLOCATOR.cache_stats()
+}
+
+// page source for the slab caches. this is called with the heap locked, so
+// nothing in here may allocate. once the slab area is used up the heap falls
+// back to its linked list
+fn alloc_slab_page() -> Option<VirtualAddress> {
+    without_interrupts(|| {
+        MEMORY_CONTROLLER.lock().as_mut().and_then(|controller| controller.alloc_slab_page())
+    })
+}
@@ -1,5 +1,11 @@
 use memory::Frame;
 use memory::PAGE_SIZE;
+use multiboot2::ElfSection;
+
+// ELF section header flags (sh_flags)
+const ELF_SECTION_WRITABLE:   u64 = 0x1;
+const ELF_SECTION_ALLOCATED:  u64 = 0x2;
+const ELF_SECTION_EXECUTABLE: u64 = 0x4;
 
 pub struct Entry(u64);
 
@@ -45,4 +51,23 @@ bitflags! {
         const GLOBAL =          1 << 8,
         const NO_EXECUTE =      1 << 63
     }
-}
\ No newline at end of file
+}
+
+impl EntryFlags {
+    pub fn from_elf_section_flags(section: &ElfSection) -> EntryFlags {
+        let mut flags = EntryFlags::empty();
+
+        if section.flags & ELF_SECTION_ALLOCATED != 0 {
+            // section is loaded into memory
+            flags = flags | PRESENT;
+        }
+        if section.flags & ELF_SECTION_WRITABLE != 0 {
+            flags = flags | WRITABLE;
+        }
+        if section.flags & ELF_SECTION_EXECUTABLE == 0 {
+            flags = flags | NO_EXECUTE;
+        }
+
+        flags
+    }
+}
@@ -1,17 +1,29 @@
 use memory::PAGE_SIZE;
 use memory::Frame;
 use memory::FrameAllocator;
+use memory::stack_allocator;
+use multiboot2::BootInformation;
+use x86::shared::{control_regs, tlb};
 
 const ENTRY_COUNT: usize = 512;
 
+// virtual address of the page used to edit inactive page tables, chosen to
+// be well away from anything else we map
+const TEMPORARY_PAGE_ADDR: VirtualAddress = 0xcafebabe;
+
+const VGA_BUFFER_ADDR: PhysicalAddress = 0xB8000;
+
 mod entry;
 mod table;
+mod temporary_page;
 
 pub type PhysicalAddress = usize;
 pub type VirtualAddress = usize;
 
 pub use self::entry::*;
+use self::temporary_page::TemporaryPage;
 
+#[derive(Debug, Clone, Copy)]
 pub struct Page(usize);
 
 impl Page {
@@ -20,10 +32,17 @@ impl Page {
         Page(address / PAGE_SIZE)
     }
 
-    fn start_address(&self) -> usize {
+    pub fn start_address(&self) -> usize {
         self.0 * PAGE_SIZE
     }
 
+    pub fn range_inclusive(start: Page, end: Page) -> PageIter {
+        PageIter {
+            start: start,
+            end: end
+        }
+    }
+
     fn p4_index(&self) -> usize {
         (self.0 >> 27) & 0o777
     }
@@ -41,6 +60,26 @@ impl Page {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct PageIter {
+    start: Page,
+    end: Page
+}
+
+impl Iterator for PageIter {
+    type Item = Page;
+
+    fn next(&mut self) -> Option<Page> {
+        if self.start.0 <= self.end.0 {
+            let page = self.start;
+            self.start.0 += 1;
+            Some(page)
+        } else {
+            None
+        }
+    }
+}
+
 use self::table::{Table, Level4};
 use core::ptr::Unique;
 
@@ -111,7 +150,7 @@ impl ActivePageTable {
         .or_else(huge_page);
 
         if let Some(ref frame) = ret_frame {
-            println!("translate page {}: frame {} (p4: {}, p3: {}, p2: {}, p1: {})",
+            trace!("translate page {}: frame {} (p4: {}, p3: {}, p2: {}, p1: {})",
             page.0, frame.0, page.p4_index(), page.p3_index(), page.p2_index(), page.p1_index());
         }
 
@@ -119,12 +158,51 @@ impl ActivePageTable {
     }
 
     pub fn map_to<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
+        // mapping a page inside a huge page splits the huge page, after which
+        // the entry for this page exists already and is replaced
+        let inside_huge_page = self.huge_page_level(page).is_some();
+
         let p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
         let p2 = p3.next_table_create(page.p3_index(), allocator);
         let p1 = p2.next_table_create(page.p2_index(), allocator);
 
-        assert!(p1[page.p1_index()].is_unused());
+        assert!(inside_huge_page || p1[page.p1_index()].is_unused());
         p1[page.p1_index()].set(frame, flags | PRESENT);
+
+        if inside_huge_page {
+            unsafe { tlb::flush(page.start_address()); }
+        }
+    }
+
+    /// Maps a 2 MiB page, both `page` and `frame` have to be 2 MiB aligned.
+    pub fn map_to_2mib<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
+        assert!(page.p1_index() == 0, "Page is not 2 MiB aligned");
+        assert!(frame.0 % ENTRY_COUNT == 0, "Frame is not 2 MiB aligned");
+
+        let inside_huge_page = self.huge_page_level(page) == Some(3);
+
+        let p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
+        let p2 = p3.next_table_create(page.p3_index(), allocator);
+
+        assert!(inside_huge_page || p2[page.p2_index()].is_unused());
+        p2[page.p2_index()].set(frame, flags | PRESENT | HUGE_PAGE);
+
+        if inside_huge_page {
+            unsafe { tlb::flush(page.start_address()); }
+        }
+    }
+
+    /// Maps a 1 GiB page, both `page` and `frame` have to be 1 GiB aligned.
+    /// Panics if the CPU doesn't support 1 GiB pages.
+    pub fn map_to_1gib<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
+        assert!(supports_1gib_pages(), "CPU does not support 1 GiB pages");
+        assert!(page.p2_index() == 0 && page.p1_index() == 0, "Page is not 1 GiB aligned");
+        assert!(frame.0 % (ENTRY_COUNT * ENTRY_COUNT) == 0, "Frame is not 1 GiB aligned");
+
+        let p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
+
+        assert!(p3[page.p3_index()].is_unused());
+        p3[page.p3_index()].set(frame, flags | PRESENT | HUGE_PAGE);
     }
 
     pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
@@ -137,30 +215,229 @@ impl ActivePageTable {
         self.map_to(page, frame, flags, allocator);
     }
 
-    fn unmap<A>(&mut self, page: Page, _allocator: &mut A) where A: FrameAllocator {
+    /// Unmaps `page` and frees the frames it pointed to. If the page is mapped
+    /// by a huge page, it has to be the first page of it and the whole huge
+    /// page is unmapped.
+    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A) where A: FrameAllocator {
+        let (Frame(start_frame_num), frame_count) = self.unmap_entry(page);
+        for frame_num in start_frame_num..start_frame_num + frame_count {
+            allocator.deallocate_frame(Frame(frame_num));
+        }
+    }
+
+    // unmaps the page and hands back the frame it pointed to instead of
+    // freeing it, for mappings of frames owned by someone else
+    fn unmap_frame(&mut self, page: Page) -> Frame {
+        self.unmap_entry(page).0
+    }
+
+    // clears whichever entry maps `page` and returns the first frame it pointed
+    // to along with the number of frames it covered
+    fn unmap_entry(&mut self, page: Page) -> (Frame, usize) {
         assert!(self.translate(page.start_address()).is_some());
 
-        let p1 = self.p4_mut()
-            .next_table_mut(page.p4_index())
-            .and_then(|p3| p3.next_table_mut(page.p3_index()))
-            .and_then(|p2| p2.next_table_mut(page.p2_index()))
-            .expect("Mapping does not support huge pages");
+        let (entry, frame_count) = {
+            let p3 = self.p4_mut().next_table_mut(page.p4_index()).unwrap();
+
+            if p3[page.p3_index()].flags().contains(HUGE_PAGE) {
+                assert!(page.p2_index() == 0 && page.p1_index() == 0, "Page is not the start of a 1 GiB page");
+                (&mut p3[page.p3_index()], ENTRY_COUNT * ENTRY_COUNT)
+            } else {
+                let p2 = p3.next_table_mut(page.p3_index()).unwrap();
+
+                if p2[page.p2_index()].flags().contains(HUGE_PAGE) {
+                    assert!(page.p1_index() == 0, "Page is not the start of a 2 MiB page");
+                    (&mut p2[page.p2_index()], ENTRY_COUNT)
+                } else {
+                    let p1 = p2.next_table_mut(page.p2_index()).unwrap();
+                    (&mut p1[page.p1_index()], 1)
+                }
+            }
+        };
 
-        // let frame = p1[page.p1_index()].pointed_frame().unwrap();
-        p1[page.p1_index()].set_unused();
+        let frame = entry.pointed_frame().unwrap();
+        entry.set_unused();
         unsafe {
-            ::x86::shared::tlb::flush(page.start_address());
+            tlb::flush(page.start_address());
+        }
+        (frame, frame_count)
+    }
+
+    // returns the level of the table holding the huge page entry which maps
+    // `page` (3 for 1 GiB pages, 2 for 2 MiB pages), if there is one
+    fn huge_page_level(&self, page: Page) -> Option<u8> {
+        let p3 = match self.p4().next_table(page.p4_index()) {
+            Some(p3) => p3,
+            None => return None
+        };
+        if p3[page.p3_index()].flags().contains(PRESENT | HUGE_PAGE) {
+            return Some(3);
+        }
+
+        match p3.next_table(page.p3_index()) {
+            Some(p2) if p2[page.p2_index()].flags().contains(PRESENT | HUGE_PAGE) => Some(2),
+            _ => None
+        }
+    }
+
+    /// Temporarily points the recursive mapping at `table`, so that `f` edits
+    /// the inactive table instead of the active one.
+    pub fn with<F>(&mut self, table: &mut InactivePageTable, temporary_page: &mut TemporaryPage, f: F)
+        where F: FnOnce(&mut ActivePageTable) {
+        {
+            let backup = Frame::for_address(unsafe { control_regs::cr3() } as usize);
+
+            // map the temporary page to the current P4 table, so we can still
+            // reach it once the recursive entry points somewhere else
+            let p4_table = temporary_page.map_table_frame(backup.clone(), self);
+
+            // overwrite the recursive mapping
+            self.p4_mut()[ENTRY_COUNT - 1].set(table.p4_frame.clone(), PRESENT | WRITABLE);
+            unsafe { tlb::flush_all(); }
+
+            f(self);
+
+            // restore the recursive mapping to the original P4 table
+            p4_table[ENTRY_COUNT - 1].set(backup, PRESENT | WRITABLE);
+            unsafe { tlb::flush_all(); }
+        }
+
+        temporary_page.unmap(self);
+    }
+
+    /// Loads `new_table` into CR3, returning the previously active table.
+    pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
+        let old_table = InactivePageTable {
+            p4_frame: Frame::for_address(unsafe { control_regs::cr3() } as usize)
+        };
+
+        unsafe {
+            control_regs::cr3_write(new_table.p4_frame.start_address() as u64);
+        }
+
+        old_table
+    }
+}
+
+pub struct InactivePageTable {
+    p4_frame: Frame
+}
+
+impl InactivePageTable {
+    pub fn new(frame: Frame, active_table: &mut ActivePageTable, temporary_page: &mut TemporaryPage) -> InactivePageTable {
+        {
+            let table = temporary_page.map_table_frame(frame.clone(), active_table);
+
+            // start out empty, except for the recursive mapping
+            table.zero();
+            table[ENTRY_COUNT - 1].set(frame.clone(), PRESENT | WRITABLE);
+        }
+        temporary_page.unmap(active_table);
+
+        InactivePageTable {
+            p4_frame: frame
         }
-        //allocator.deallocate_frame(frame);
     }
 }
 
+/// Builds a fresh set of page tables which map every ELF section of the kernel
+/// with the permissions derived from its flags, identity maps the VGA buffer,
+/// the multiboot info and every physical range in `identity_ranges`, and
+/// switches to them.
+pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation,
+                           identity_ranges: &[(PhysicalAddress, PhysicalAddress)]) -> ActivePageTable
+    where A: FrameAllocator {
+    // make the NO_EXECUTE and read only flags actually do something
+    enable_nxe_bit();
+    enable_write_protect_bit();
+
+    let mut temporary_page = TemporaryPage::new(Page::for_address(TEMPORARY_PAGE_ADDR), allocator);
+
+    let mut active_table = unsafe { ActivePageTable::new() };
+    let mut new_table = {
+        let frame = allocator.allocate_frame().expect("No free frames");
+        InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
+    };
+
+    active_table.with(&mut new_table, &mut temporary_page, |mapper| {
+        let elf_sections_tag = boot_info.elf_sections_tag().expect("ELF sections tag required");
+
+        for section in elf_sections_tag.sections() {
+            let flags = EntryFlags::from_elf_section_flags(section);
+            if !flags.contains(PRESENT) || section.size == 0 {
+                // section is not loaded into memory
+                continue;
+            }
+
+            let start = section.addr as usize;
+            let end = (section.addr + section.size) as usize;
+            assert!(start % PAGE_SIZE == 0, "Kernel sections need to be page aligned");
+
+            debug!("mapping section at addr: 0x{:x}, size: 0x{:x}, flags: {:?}", start, section.size, flags);
+
+            let start_frame = Frame::for_address(start);
+            let end_frame = Frame::for_address(end - 1);
+            for frame in Frame::range_inclusive(start_frame, end_frame) {
+                mapper.identity_map(frame, flags, allocator);
+            }
+        }
+
+        mapper.identity_map(Frame::for_address(VGA_BUFFER_ADDR), WRITABLE | NO_EXECUTE, allocator);
+
+        let mb_start = Frame::for_address(boot_info.start_address());
+        let mb_end = Frame::for_address(boot_info.end_address() - 1);
+        for frame in Frame::range_inclusive(mb_start, mb_end) {
+            mapper.identity_map(frame, NO_EXECUTE, allocator);
+        }
+
+        for &(start, end) in identity_ranges {
+            for frame in Frame::range_inclusive(Frame::for_address(start), Frame::for_address(end)) {
+                mapper.identity_map(frame, WRITABLE | NO_EXECUTE, allocator);
+            }
+        }
+    });
+
+    active_table.switch(new_table);
+    println!("Switched to the remapped kernel page table");
+
+    // the page below the boot stack is part of the kernel's .bss, so it was
+    // mapped with the rest of the section above. unmap it without freeing
+    // its frame, so that overflowing the stack faults instead of corrupting
+    // whatever lies below it
+    let guard_page = stack_allocator::boot_stack_guard_page();
+    active_table.unmap_frame(guard_page);
+    println!("Boot stack guard page at 0x{:x}", guard_page.start_address());
+
+    active_table
+}
+
+// checks the pdpe1gb bit of the extended processor info
+fn supports_1gib_pages() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    unsafe { __cpuid(0x8000_0001).edx & (1 << 26) != 0 }
+}
+
+fn enable_nxe_bit() {
+    use x86::shared::msr::{IA32_EFER, rdmsr, wrmsr};
+
+    let nxe_bit = 1 << 11;
+    unsafe {
+        let efer = rdmsr(IA32_EFER);
+        wrmsr(IA32_EFER, efer | nxe_bit);
+    }
+}
+
+fn enable_write_protect_bit() {
+    use x86::shared::control_regs::{cr0, cr0_write, CR0_WRITE_PROTECT};
+
+    unsafe { cr0_write(cr0() | CR0_WRITE_PROTECT) };
+}
 
-pub fn test_paging<A>(allocator: &mut A) where A: FrameAllocator {
-    let mut page_table = unsafe { ActivePageTable::new() };
 
-    test_map(&mut page_table, allocator);
-    test_translate(&page_table);
+pub fn test_paging<A>(page_table: &mut ActivePageTable, allocator: &mut A) where A: FrameAllocator {
+    test_map(page_table, allocator);
+    test_translate(page_table);
 }
 
 fn test_translate(page_table: &ActivePageTable) {
@@ -2,7 +2,8 @@ use core::marker::PhantomData;
 
 use memory::paging::entry::*;
 use memory::paging::ENTRY_COUNT;
-use memory::FrameAllocator;
+use memory::{Frame, FrameAllocator};
+use x86::shared::tlb;
 
 pub const P4: *mut Table<Level4> = 0xFFFFFFFF_FFFFF000 as *mut _;
 
@@ -96,13 +97,45 @@ impl<L> Table<L> where L: HeirarchicalLevel {
 
     pub fn next_table_create<A>(&mut self, index: usize, allocator: &mut A) -> &mut Table<L::NextLevel> where A: FrameAllocator {
         if self.next_table(index).is_none() {
-            assert!(!self.entries[index].flags().contains(HUGE_PAGE), "mapping does not support huge pages");
             let frame = allocator.allocate_frame().expect("no frames available");
-            println!("Creating new level {} page table in frame {}", L::LEVEL - 1, frame.0);
-            self.entries[index].set(frame, PRESENT | WRITABLE);
-            self.next_table_mut(index).unwrap().zero();
+            if self.entries[index].flags().contains(HUGE_PAGE) {
+                self.split_huge_page(index, frame);
+            } else {
+                trace!("Creating new level {} page table in frame {}", L::LEVEL - 1, frame.0);
+                self.entries[index].set(frame, PRESENT | WRITABLE);
+                self.next_table_mut(index).unwrap().zero();
+            }
         }
 
         self.next_table_mut(index).unwrap()
     }
+
+    // replaces the huge page entry at `index` with a table of 512 entries
+    // which map the same memory with the same flags, using `frame` for the new table
+    fn split_huge_page(&mut self, index: usize, frame: Frame) {
+        let Frame(start_frame_num) = self.entries[index].pointed_frame().unwrap();
+        let flags = self.entries[index].flags();
+
+        // a 1 GiB page is split into 2 MiB pages, which are still huge,
+        // a 2 MiB page is split into regular 4 KiB pages
+        let (frames_per_entry, entry_flags) = if L::LEVEL == 3 {
+            (ENTRY_COUNT, flags)
+        } else {
+            (1, flags & !HUGE_PAGE)
+        };
+
+        trace!("Splitting huge level {} page at frame {} using frame {}", L::LEVEL, start_frame_num, frame.0);
+
+        // the final permissions are set on the new entries, the table entry itself stays permissive
+        self.entries[index].set(frame, PRESENT | WRITABLE);
+        unsafe { tlb::flush_all(); }
+
+        {
+            let table = self.next_table_mut(index).unwrap();
+            for (i, entry) in table.entries.iter_mut().enumerate() {
+                entry.set(Frame(start_frame_num + i * frames_per_entry), entry_flags);
+            }
+        }
+        unsafe { tlb::flush_all(); }
+    }
 }
@@ -0,0 +1,73 @@
+use memory::{Frame, FrameAllocator};
+use memory::paging::{Page, ActivePageTable, VirtualAddress};
+use memory::paging::entry::WRITABLE;
+use memory::paging::table::{Table, Level1};
+
+/// A single page which can be temporarily mapped to an arbitrary frame, used
+/// to edit page tables which aren't reachable through the recursive mapping.
+pub struct TemporaryPage {
+    page: Page,
+    allocator: TinyAllocator
+}
+
+impl TemporaryPage {
+    pub fn new<A>(page: Page, allocator: &mut A) -> TemporaryPage where A: FrameAllocator {
+        TemporaryPage {
+            page: page,
+            allocator: TinyAllocator::new(allocator)
+        }
+    }
+
+    /// Maps the temporary page to the given frame in the active table.
+    /// Returns the start address of the temporary page.
+    pub fn map(&mut self, frame: Frame, active_table: &mut ActivePageTable) -> VirtualAddress {
+        assert!(active_table.translate_page(self.page).is_none(), "temporary page is already mapped");
+        active_table.map_to(self.page, frame, WRITABLE, &mut self.allocator);
+        self.page.start_address()
+    }
+
+    /// Maps the temporary page to the given page table frame in the active
+    /// table. Returns a reference to the now mapped table.
+    pub fn map_table_frame(&mut self, frame: Frame, active_table: &mut ActivePageTable) -> &mut Table<Level1> {
+        unsafe { &mut *(self.map(frame, active_table) as *mut Table<Level1>) }
+    }
+
+    /// Unmaps the temporary page in the active table. The frame it pointed to
+    /// is left alone, since it still belongs to whoever handed it to `map`.
+    pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
+        active_table.unmap_frame(self.page);
+    }
+}
+
+// holds the (at most three) frames needed to create the page tables for the
+// temporary page, so that mapping it never has to go back to the real allocator
+struct TinyAllocator([Option<Frame>; 3]);
+
+impl TinyAllocator {
+    fn new<A>(allocator: &mut A) -> TinyAllocator where A: FrameAllocator {
+        let mut f = || allocator.allocate_frame();
+        let frames = [f(), f(), f()];
+        TinyAllocator(frames)
+    }
+}
+
+impl FrameAllocator for TinyAllocator {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        for frame_option in &mut self.0 {
+            if frame_option.is_some() {
+                return frame_option.take();
+            }
+        }
+        None
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        for frame_option in &mut self.0 {
+            if frame_option.is_none() {
+                *frame_option = Some(frame);
+                return;
+            }
+        }
+        panic!("Tiny allocator can hold only 3 frames.");
+    }
+}
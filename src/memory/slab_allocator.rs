@@ -0,0 +1,174 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr;
+
+use memory::PAGE_SIZE;
+use memory::heap_allocator::LinkedListAllocator;
+use memory::paging::VirtualAddress;
+
+/// Number of size classes, from 8 bytes up to 2 KiB in powers of two.
+pub const CACHE_COUNT: usize = 9;
+
+const MIN_OBJECT_SIZE: usize = 8;
+const MAX_OBJECT_SIZE: usize = MIN_OBJECT_SIZE << (CACHE_COUNT - 1);
+
+/// Maps a fresh page for a slab cache and returns its address.
+pub type PageSource = fn() -> Option<VirtualAddress>;
+
+/// Usage counters of a single slab cache.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub object_size: usize,
+    pub pages: usize,
+    pub objects_in_use: usize,
+    pub objects_free: usize
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "slab-{}: {} pages, {} in use, {} free",
+               self.object_size, self.pages, self.objects_in_use, self.objects_free)
+    }
+}
+
+// link stored in every free object of a slab
+struct FreeObject {
+    next: *mut FreeObject
+}
+
+/// Cache of equally sized objects carved out of whole pages.
+///
+/// Objects are laid out back to back from the start of each page, so with
+/// power of two sizes every object is aligned to its own size.
+struct SlabCache {
+    object_size: usize,
+    free_list: *mut FreeObject,
+    stats: CacheStats
+}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> SlabCache {
+        SlabCache {
+            object_size: object_size,
+            free_list: ptr::null_mut(),
+            stats: CacheStats { object_size: object_size, pages: 0, objects_in_use: 0, objects_free: 0 }
+        }
+    }
+
+    fn allocate(&mut self, page_source: PageSource) -> Option<usize> {
+        if self.free_list.is_null() {
+            let page = page_source()?;
+            self.grow(page);
+        }
+
+        let object = self.free_list;
+        unsafe {
+            self.free_list = (*object).next;
+        }
+        self.stats.objects_in_use += 1;
+        self.stats.objects_free -= 1;
+        Some(object as usize)
+    }
+
+    unsafe fn deallocate(&mut self, addr: usize) {
+        let object = addr as *mut FreeObject;
+        ptr::write(object, FreeObject { next: self.free_list });
+        self.free_list = object;
+        self.stats.objects_in_use -= 1;
+        self.stats.objects_free += 1;
+    }
+
+    // splits a new page into objects and puts all of them on the free list
+    fn grow(&mut self, page: VirtualAddress) {
+        let objects = PAGE_SIZE / self.object_size;
+        for i in (0..objects).rev() {
+            let object = (page + i * self.object_size) as *mut FreeObject;
+            unsafe {
+                ptr::write(object, FreeObject { next: self.free_list });
+            }
+            self.free_list = object;
+        }
+
+        self.stats.pages += 1;
+        self.stats.objects_free += objects;
+    }
+}
+
+/// Serves small allocations from power of two slab caches and everything
+/// larger than `MAX_OBJECT_SIZE` from a general purpose linked list heap.
+///
+/// Small allocations also fall back to the linked list heap once the page
+/// source runs out, so objects are freed by where they live, not their size.
+pub struct SlabAllocator {
+    caches: [SlabCache; CACHE_COUNT],
+    fallback: LinkedListAllocator,
+    // range of the linked list heap, `[start, end)`
+    heap_range: (usize, usize),
+    page_source: Option<PageSource>
+}
+
+// the raw pointers only ever point into pages owned by the allocator
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn empty() -> SlabAllocator {
+        SlabAllocator {
+            caches: [
+                SlabCache::new(8), SlabCache::new(16), SlabCache::new(32),
+                SlabCache::new(64), SlabCache::new(128), SlabCache::new(256),
+                SlabCache::new(512), SlabCache::new(1024), SlabCache::new(2048)
+            ],
+            fallback: LinkedListAllocator::empty(),
+            heap_range: (0, 0),
+            page_source: None
+        }
+    }
+
+    /// Unsafe because the heap range has to be mapped and unused, and
+    /// `page_source` has to hand out mapped pages nobody else uses.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize, page_source: PageSource) {
+        self.fallback.init(heap_start, heap_size);
+        self.heap_range = (heap_start, heap_start + heap_size);
+        self.page_source = Some(page_source);
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<usize> {
+        let object = match (SlabAllocator::cache_index(&layout), self.page_source) {
+            (Some(index), Some(page_source)) => self.caches[index].allocate(page_source),
+            _ => None
+        };
+        object.or_else(|| self.fallback.allocate(layout))
+    }
+
+    pub unsafe fn deallocate(&mut self, addr: usize, layout: Layout) {
+        let (heap_start, heap_end) = self.heap_range;
+        if heap_start <= addr && addr < heap_end {
+            self.fallback.deallocate(addr, layout);
+        } else {
+            let index = SlabAllocator::cache_index(&layout).expect("Freed object outside of the heap and slabs");
+            self.caches[index].deallocate(addr);
+        }
+    }
+
+    pub fn cache_stats(&self) -> [CacheStats; CACHE_COUNT] {
+        let mut stats = [self.caches[0].stats; CACHE_COUNT];
+        for (stats, cache) in stats.iter_mut().zip(self.caches.iter()) {
+            *stats = cache.stats;
+        }
+        stats
+    }
+
+    // smallest size class which fits both the size and the alignment of the layout
+    fn cache_index(layout: &Layout) -> Option<usize> {
+        let size = if layout.size() > layout.align() { layout.size() } else { layout.align() };
+        if size > MAX_OBJECT_SIZE {
+            return None;
+        }
+
+        let mut index = 0;
+        while MIN_OBJECT_SIZE << index < size {
+            index += 1;
+        }
+        Some(index)
+    }
+}
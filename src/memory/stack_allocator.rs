@@ -0,0 +1,119 @@
+use memory::{PAGE_SIZE, FrameAllocator};
+use memory::paging::{Page, PageIter, ActivePageTable, VirtualAddress, WRITABLE, NO_EXECUTE};
+
+extern {
+    // defined in boot.asm, directly below the boot stack
+    static stack_guard_page: u8;
+}
+
+/// Virtual address range reserved for kernel stacks handed out by `StackAllocator`.
+pub const KERNEL_STACKS_START: VirtualAddress = 0x0000_0100_0000_0000;
+pub const KERNEL_STACKS_SIZE: usize = 1024 * PAGE_SIZE;
+
+// one bit per page of the stack area, set for the guard pages
+const GUARD_WORDS: usize = KERNEL_STACKS_SIZE / PAGE_SIZE / 64;
+
+/// The unmapped page directly below the stack the kernel booted on.
+pub fn boot_stack_guard_page() -> Page {
+    Page::for_address(unsafe { &stack_guard_page as *const u8 as usize })
+}
+
+/// Hands out kernel stacks from a range of pages, leaving an unmapped guard
+/// page below each one.
+pub struct StackAllocator {
+    range: PageIter,
+    first_page: Page,
+    // bit n is set if page n of the range is the guard page of a stack
+    guard_pages: [u64; GUARD_WORDS]
+}
+
+impl StackAllocator {
+    /// Takes a range of at most `KERNEL_STACKS_SIZE` bytes.
+    pub fn new(page_range: PageIter) -> StackAllocator {
+        let first_page = page_range.clone().next().expect("Empty kernel stack range");
+        assert!(page_range.clone().count() <= GUARD_WORDS * 64, "Kernel stack range too large");
+
+        StackAllocator {
+            range: page_range,
+            first_page: first_page,
+            guard_pages: [0; GUARD_WORDS]
+        }
+    }
+
+    /// Checks whether `address` lies in the guard page below one of the
+    /// stacks handed out so far.
+    pub fn is_guard_page(&self, address: VirtualAddress) -> bool {
+        let first = self.first_page.start_address();
+        if address < first {
+            return false;
+        }
+
+        let index = (address - first) / PAGE_SIZE;
+        index < GUARD_WORDS * 64 && self.guard_pages[index / 64] & 1 << (index % 64) != 0
+    }
+
+    pub fn alloc_stack<A>(&mut self, active_table: &mut ActivePageTable, frame_allocator: &mut A,
+                          size_in_pages: usize) -> Option<Stack> where A: FrameAllocator {
+        if size_in_pages == 0 {
+            return None;
+        }
+
+        // clone the range, since we only want to change it on success
+        let mut range = self.range.clone();
+
+        // try to allocate the stack pages and a guard page
+        let guard_page = range.next();
+        let stack_start = range.next();
+        let stack_end = if size_in_pages == 1 {
+            stack_start
+        } else {
+            // choose the (size_in_pages-2)th element, since index starts at 0
+            // and we already allocated the start page
+            range.nth(size_in_pages - 2)
+        };
+
+        match (guard_page, stack_start, stack_end) {
+            (Some(guard), Some(start), Some(end)) => {
+                // success! write back updated range
+                self.range = range;
+
+                let index = (guard.start_address() - self.first_page.start_address()) / PAGE_SIZE;
+                self.guard_pages[index / 64] |= 1 << (index % 64);
+
+                // map stack pages to physical frames, the guard page stays unmapped
+                for page in Page::range_inclusive(start, end) {
+                    active_table.map(page, WRITABLE | NO_EXECUTE, frame_allocator);
+                }
+
+                // create a new stack
+                let top_of_stack = end.start_address() + PAGE_SIZE;
+                Some(Stack::new(top_of_stack, start.start_address()))
+            }
+            _ => None // not enough pages
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Stack {
+    top: VirtualAddress,
+    bottom: VirtualAddress
+}
+
+impl Stack {
+    fn new(top: VirtualAddress, bottom: VirtualAddress) -> Stack {
+        assert!(top > bottom);
+        Stack {
+            top: top,
+            bottom: bottom
+        }
+    }
+
+    pub fn top(&self) -> VirtualAddress {
+        self.top
+    }
+
+    pub fn bottom(&self) -> VirtualAddress {
+        self.bottom
+    }
+}
@@ -0,0 +1,151 @@
+use core::{cmp, fmt};
+
+use memory::{Frame, PAGE_SIZE};
+use multiboot2::MemoryAreaIter;
+
+/// Maximum number of memory map areas tracked individually in `MemoryStats`.
+pub const MAX_MEMORY_AREAS: usize = 32;
+
+/// Frame counters for a single usable area of the multiboot2 memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct AreaStats {
+    pub base_addr: u64,
+    pub length: u64,
+    pub total_frames: usize,
+    pub free_frames: usize
+}
+
+/// Snapshot of physical memory usage as seen by a frame allocator.
+///
+/// Every usable frame is counted in exactly one of the free, allocated or
+/// reserved buckets, so they always add up to `total_frames`.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryStats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    pub allocated_frames: usize,
+    pub kernel_frames: usize,
+    pub multiboot_frames: usize,
+    pub allocator_frames: usize,
+    areas: [AreaStats; MAX_MEMORY_AREAS],
+    area_count: usize
+}
+
+impl MemoryStats {
+    /// Walks every usable frame in `memory_areas` below `frame_limit` and sorts
+    /// it into a bucket, counting every frame that isn't reserved as free.
+    /// `mb_ranges` are the inclusive frame number ranges of the multiboot
+    /// information and the boot modules.
+    ///
+    /// Allocators call this once when they're created and then keep the
+    /// counters up to date with `count_allocated` and `count_freed`, so
+    /// reading them never has to walk physical memory.
+    pub fn collect(memory_areas: MemoryAreaIter, kernel_range: &(Frame, Frame), mb_ranges: &[(usize, usize)],
+                   allocator_range: Option<&(Frame, Frame)>, frame_limit: usize) -> MemoryStats {
+        let empty_area = AreaStats { base_addr: 0, length: 0, total_frames: 0, free_frames: 0 };
+        let mut stats = MemoryStats {
+            total_frames: 0,
+            free_frames: 0,
+            allocated_frames: 0,
+            kernel_frames: 0,
+            multiboot_frames: 0,
+            allocator_frames: 0,
+            areas: [empty_area; MAX_MEMORY_AREAS],
+            area_count: 0
+        };
+
+        let contains = |range: &(Frame, Frame), frame_num: usize| {
+            let &(Frame(start), Frame(end)) = range;
+            start <= frame_num && frame_num <= end
+        };
+
+        for area in memory_areas {
+            let mut area_stats = AreaStats { base_addr: area.base_addr, length: area.length, .. empty_area };
+
+            // only frames which lie completely inside the area are usable
+            let start = (area.base_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+            let end = cmp::min((area.base_addr + area.length) as usize / PAGE_SIZE, frame_limit);
+
+            for frame_num in start..end {
+                area_stats.total_frames += 1;
+
+                if contains(kernel_range, frame_num) {
+                    stats.kernel_frames += 1;
+                } else if mb_ranges.iter().any(|&(start, end)| start <= frame_num && frame_num <= end) {
+                    stats.multiboot_frames += 1;
+                } else if allocator_range.map_or(false, |range| contains(range, frame_num)) {
+                    stats.allocator_frames += 1;
+                } else {
+                    area_stats.free_frames += 1;
+                }
+            }
+
+            stats.total_frames += area_stats.total_frames;
+            stats.free_frames += area_stats.free_frames;
+
+            if stats.area_count < MAX_MEMORY_AREAS {
+                stats.areas[stats.area_count] = area_stats;
+                stats.area_count += 1;
+            }
+        }
+
+        stats
+    }
+
+    /// Moves a frame which was handed out from the free to the allocated count.
+    pub fn count_allocated(&mut self, frame_num: usize) {
+        self.free_frames -= 1;
+        self.allocated_frames += 1;
+        if let Some(area) = self.area_for(frame_num) {
+            area.free_frames -= 1;
+        }
+    }
+
+    /// Moves a frame which was given back from the allocated to the free count.
+    pub fn count_freed(&mut self, frame_num: usize) {
+        self.free_frames += 1;
+        self.allocated_frames -= 1;
+        if let Some(area) = self.area_for(frame_num) {
+            area.free_frames += 1;
+        }
+    }
+
+    /// Per-area counters, in memory map order.
+    pub fn areas(&self) -> &[AreaStats] {
+        &self.areas[..self.area_count]
+    }
+
+    // areas past MAX_MEMORY_AREAS aren't tracked individually
+    fn area_for(&mut self, frame_num: usize) -> Option<&mut AreaStats> {
+        let address = (frame_num * PAGE_SIZE) as u64;
+        self.areas[..self.area_count].iter_mut()
+            .find(|area| area.base_addr <= address && address < area.base_addr + area.length)
+    }
+}
+
+// frame counts are printed alongside their size in KiB
+struct Frames(usize);
+
+impl fmt::Display for Frames {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} frames ({} KiB)", self.0, self.0 * PAGE_SIZE / 1024)
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "meminfo:")?;
+        writeln!(f, "    usable:    {}", Frames(self.total_frames))?;
+        writeln!(f, "    free:      {}", Frames(self.free_frames))?;
+        writeln!(f, "    allocated: {}", Frames(self.allocated_frames))?;
+        writeln!(f, "    kernel:    {}", Frames(self.kernel_frames))?;
+        writeln!(f, "    multiboot: {}", Frames(self.multiboot_frames))?;
+        writeln!(f, "    allocator: {}", Frames(self.allocator_frames))?;
+        write!(f, "    areas:")?;
+        for area in self.areas() {
+            write!(f, "\n        start: 0x{:x}, length: 0x{:x}, frames: {}, free: {}",
+                   area.base_addr, area.length, area.total_frames, area.free_frames)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ *  Driver for 16550 compatible UARTs.
+ *
+ *  Output is polled, which keeps it usable from anywhere including the panic
+ *  handler. Received bytes are collected by the IRQ handler into a small ring
+ *  buffer once receive interrupts are enabled.
+ */
+
+use core::fmt;
+
+use spin::Mutex;
+use x86::shared::io::{inb, outb};
+
+use interrupts::{self, without_interrupts};
+
+pub const COM1_BASE: u16 = 0x3F8;
+const COM1_IRQ: u8 = 4;
+
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+const UART_CLOCK: u32 = 115_200;
+
+// register offsets from the base port
+const REG_DATA: u16 = 0;
+const REG_INTERRUPT_ENABLE: u16 = 1;
+const REG_FIFO_CONTROL: u16 = 2;
+const REG_LINE_CONTROL: u16 = 3;
+const REG_MODEM_CONTROL: u16 = 4;
+const REG_LINE_STATUS: u16 = 5;
+// with DLAB set, the first two registers hold the baud rate divisor
+const REG_DIVISOR_LOW: u16 = 0;
+const REG_DIVISOR_HIGH: u16 = 1;
+
+const INTERRUPT_DATA_AVAILABLE: u8 = 1 << 0;
+const LINE_CONTROL_8N1: u8 = 0b11;
+const LINE_CONTROL_DLAB: u8 = 1 << 7;
+// enable and clear both FIFOs, interrupt at 14 received bytes
+const FIFO_ENABLE_CLEAR_14: u8 = 0xC7;
+// DTR, RTS and OUT2, which gates the UART's interrupt line
+const MODEM_CONTROL_NORMAL: u8 = 0x0B;
+const MODEM_CONTROL_LOOPBACK: u8 = 0x1E;
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+const RX_BUFFER_SIZE: usize = 256;
+
+pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1_BASE));
+static RX_BUFFER: Mutex<RingBuffer> = Mutex::new(RingBuffer::new());
+
+macro_rules! serial_print {
+    ($($arg:tt)*) => ({
+        $crate::serial::print(format_args!($($arg)*));
+    });
+}
+
+macro_rules! serial_println {
+    ($fmt:expr) => (serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    without_interrupts(|| COM1.lock().write_fmt(args).unwrap());
+}
+
+/// Writes to COM1 without taking its lock, for the panic handler, which may
+/// run while the lock is held. Output may interleave with other writers.
+pub unsafe fn force_print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    SerialPort::new(COM1_BASE).write_fmt(args).unwrap();
+}
+
+/// Sets up COM1 with the default baud rate.
+pub fn init() {
+    if !COM1.lock().init(DEFAULT_BAUD_RATE) {
+        // nothing to see it, but the writes are harmless
+        println!("serial: no UART at 0x{:x}", COM1_BASE);
+    }
+}
+
+/// Starts collecting received bytes from COM1 in the background. Interrupts
+/// have to be initialized first.
+pub fn enable_rx_interrupts() {
+    interrupts::register_handler(COM1_IRQ, com1_interrupt);
+    without_interrupts(|| COM1.lock().enable_rx_interrupts());
+}
+
+/// Returns the next byte received on COM1, if any.
+pub fn read_byte() -> Option<u8> {
+    without_interrupts(|| RX_BUFFER.lock().pop())
+}
+
+fn com1_interrupt(_irq: u8) {
+    let com1 = COM1.lock();
+    let mut rx_buffer = RX_BUFFER.lock();
+    while let Some(byte) = com1.try_read_byte() {
+        rx_buffer.push(byte);
+    }
+}
+
+pub struct SerialPort {
+    base: u16
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> SerialPort {
+        SerialPort { base: base }
+    }
+
+    /// Configures 8N1 at `baud_rate` with FIFOs enabled and interrupts off.
+    /// Returns false if the UART failed its loopback test.
+    pub fn init(&mut self, baud_rate: u32) -> bool {
+        let divisor = (UART_CLOCK / baud_rate) as u16;
+        unsafe {
+            self.write_register(REG_INTERRUPT_ENABLE, 0);
+            self.write_register(REG_LINE_CONTROL, LINE_CONTROL_DLAB);
+            self.write_register(REG_DIVISOR_LOW, divisor as u8);
+            self.write_register(REG_DIVISOR_HIGH, (divisor >> 8) as u8);
+            self.write_register(REG_LINE_CONTROL, LINE_CONTROL_8N1);
+            self.write_register(REG_FIFO_CONTROL, FIFO_ENABLE_CLEAR_14);
+
+            // a byte sent in loopback mode has to come back unchanged
+            self.write_register(REG_MODEM_CONTROL, MODEM_CONTROL_LOOPBACK);
+            self.write_register(REG_DATA, 0xAE);
+            let works = self.read_register(REG_DATA) == 0xAE;
+
+            self.write_register(REG_MODEM_CONTROL, MODEM_CONTROL_NORMAL);
+            works
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.read_register(REG_LINE_STATUS) & LINE_STATUS_TRANSMIT_EMPTY == 0 {}
+            self.write_register(REG_DATA, byte);
+        }
+    }
+
+    /// Returns a received byte without waiting, if there is one.
+    pub fn try_read_byte(&self) -> Option<u8> {
+        unsafe {
+            if self.read_register(REG_LINE_STATUS) & LINE_STATUS_DATA_READY != 0 {
+                Some(self.read_register(REG_DATA))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn enable_rx_interrupts(&mut self) {
+        unsafe { self.write_register(REG_INTERRUPT_ENABLE, INTERRUPT_DATA_AVAILABLE); }
+    }
+
+    unsafe fn read_register(&self, register: u16) -> u8 {
+        inb(self.base + register)
+    }
+
+    unsafe fn write_register(&mut self, register: u16, value: u8) {
+        outb(self.base + register, value);
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // terminals expect CRLF line endings
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+// fixed size byte queue, the oldest bytes are dropped when it's full
+struct RingBuffer {
+    data: [u8; RX_BUFFER_SIZE],
+    head: usize,
+    len: usize
+}
+
+impl RingBuffer {
+    const fn new() -> RingBuffer {
+        RingBuffer { data: [0; RX_BUFFER_SIZE], head: 0, len: 0 }
+    }
+
+    fn push(&mut self, byte: u8) {
+        let tail = (self.head + self.len) % RX_BUFFER_SIZE;
+        self.data[tail] = byte;
+        if self.len == RX_BUFFER_SIZE {
+            self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ *  Local APIC timer.
+ *
+ *  Used as a one-shot timer, either counting down from an initial count at
+ *  a rate calibrated against the HPET or PIT, or in TSC-deadline mode where it fires
+ *  once the TSC reaches a given value.
+ */
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::u32;
+
+use x86::shared::msr::wrmsr;
+
+use interrupts::{self, LocalApic, APIC_TIMER_VECTOR};
+use time::{self, tsc};
+
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3E0;
+
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_ONE_SHOT: u32 = 0b00 << 17;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+const DIVIDE_BY_16: u32 = 0b0011;
+
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+const CALIBRATION_MS: u64 = 10;
+
+// timer counts per second after the divider, 0 in TSC-deadline mode
+static FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+static TSC_DEADLINE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the timer to call `handler` when it fires. Returns false if there
+/// is no local APIC. The TSC has to be calibrated first.
+pub fn init(handler: fn()) -> bool {
+    let lapic = match interrupts::local_apic() {
+        Some(lapic) => lapic,
+        None => return false
+    };
+    interrupts::set_timer_handler(handler);
+
+    if supports_tsc_deadline() {
+        lapic.write(LAPIC_LVT_TIMER, LVT_TIMER_TSC_DEADLINE | APIC_TIMER_VECTOR as u32);
+        TSC_DEADLINE_MODE.store(true, Ordering::Relaxed);
+        println!("apic timer: TSC-deadline mode");
+    } else {
+        let frequency = calibrate(lapic);
+        FREQUENCY.store(frequency as usize, Ordering::Relaxed);
+        lapic.write(LAPIC_LVT_TIMER, LVT_TIMER_ONE_SHOT | APIC_TIMER_VECTOR as u32);
+        println!("apic timer: one-shot mode at {} kHz", frequency / 1000);
+    }
+    true
+}
+
+/// Makes the timer fire once the TSC reaches `deadline`, replacing any
+/// deadline set before.
+pub fn set_deadline(deadline: u64) {
+    let lapic = interrupts::local_apic().expect("APIC not initialized");
+
+    if TSC_DEADLINE_MODE.load(Ordering::Relaxed) {
+        unsafe { wrmsr(IA32_TSC_DEADLINE, deadline); }
+    } else {
+        let cycles = deadline.saturating_sub(tsc::read()) as u128;
+        let tsc_frequency = tsc::frequency().expect("TSC not calibrated") as u128;
+        let count = cycles * FREQUENCY.load(Ordering::Relaxed) as u128 / tsc_frequency;
+
+        // a count of 0 stops the timer, and if the deadline is further away
+        // than the counter reaches, the callback just sets it again
+        let count = if count == 0 { 1 } else if count > u32::MAX as u128 { u32::MAX } else { count as u32 };
+        lapic.write(LAPIC_TIMER_INITIAL_COUNT, count);
+    }
+}
+
+pub fn stop() {
+    let lapic = interrupts::local_apic().expect("APIC not initialized");
+
+    if TSC_DEADLINE_MODE.load(Ordering::Relaxed) {
+        unsafe { wrmsr(IA32_TSC_DEADLINE, 0); }
+    } else {
+        lapic.write(LAPIC_TIMER_INITIAL_COUNT, 0);
+    }
+}
+
+// counts how far the timer gets in a known amount of time
+fn calibrate(lapic: &LocalApic) -> u64 {
+    lapic.write(LAPIC_TIMER_DIVIDE, DIVIDE_BY_16);
+    lapic.write(LAPIC_LVT_TIMER, LVT_MASKED | APIC_TIMER_VECTOR as u32);
+
+    time::calibration_wait(CALIBRATION_MS, || lapic.write(LAPIC_TIMER_INITIAL_COUNT, u32::MAX));
+    let elapsed = u32::MAX - lapic.read(LAPIC_TIMER_CURRENT_COUNT);
+    lapic.write(LAPIC_TIMER_INITIAL_COUNT, 0);
+
+    elapsed as u64 * 1000 / CALIBRATION_MS
+}
+
+fn supports_tsc_deadline() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    unsafe { __cpuid(1).ecx & (1 << 24) != 0 }
+}
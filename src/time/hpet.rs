@@ -0,0 +1,157 @@
+/*
+ *  High Precision Event Timer.
+ *
+ *  A free running main counter with a fixed period of at most 100 ns and a
+ *  set of comparators. Comparator 0 runs in legacy replacement mode, where it
+ *  takes the place of the PIT on IRQ 0.
+ */
+
+use core::ptr;
+use core::time::Duration;
+
+use spin::Once;
+
+use acpi::hpet::HpetTable;
+use memory::{self, PhysicalAddress, PAGE_SIZE, WRITABLE, NO_CACHE, NO_EXECUTE};
+
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIG: usize = 0x010;
+const REG_MAIN_COUNTER: usize = 0x0F0;
+
+const CAP_COUNTER_64BIT: u64 = 1 << 13;
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_SET_ACCUMULATOR: u64 = 1 << 6;
+
+// the spec caps the counter period at 100 ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+struct Hpet {
+    base: usize,
+    period_fs: u64,
+    counter_64bit: bool,
+    minimum_tick: u64
+}
+
+impl Hpet {
+    fn read(&self, register: usize) -> u64 {
+        unsafe { ptr::read_volatile((self.base + register) as *const u64) }
+    }
+
+    fn write(&self, register: usize, value: u64) {
+        unsafe { ptr::write_volatile((self.base + register) as *mut u64, value) }
+    }
+
+    fn timer_config(timer: usize) -> usize {
+        0x100 + 0x20 * timer
+    }
+
+    fn timer_comparator(timer: usize) -> usize {
+        0x108 + 0x20 * timer
+    }
+}
+
+static HPET: Once<Hpet> = Once::new();
+
+/// Maps and starts the HPET described by ACPI. Returns false if there is none.
+pub fn init() -> bool {
+    let table = match HpetTable::parse() {
+        Some(table) => table,
+        None => return false
+    };
+
+    let hpet = Hpet { base: map_registers(table.address), period_fs: 0, counter_64bit: false, minimum_tick: 0 };
+    let capabilities = hpet.read(REG_CAPABILITIES);
+    let period_fs = capabilities >> 32;
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        println!("hpet: invalid counter period of {} fs", period_fs);
+        return false;
+    }
+
+    let hpet = HPET.call_once(|| Hpet {
+        period_fs: period_fs,
+        counter_64bit: capabilities & CAP_COUNTER_64BIT != 0,
+        minimum_tick: table.minimum_tick as u64,
+        ..hpet
+    });
+
+    hpet.write(REG_CONFIG, hpet.read(REG_CONFIG) | CONFIG_ENABLE);
+
+    println!("hpet: {} at 0x{:x}, {} comparators, {} MHz, {} bit counter",
+             table.hpet_number, table.address, ((capabilities >> 8) & 0x1F) + 1,
+             1_000_000_000 / period_fs, if hpet.counter_64bit { 64 } else { 32 });
+    true
+}
+
+pub fn is_available() -> bool {
+    HPET.try().is_some()
+}
+
+/// Current value of the main counter.
+pub fn counter() -> u64 {
+    hpet().read(REG_MAIN_COUNTER)
+}
+
+/// Length of a counter tick in femtoseconds.
+pub fn period_fs() -> u64 {
+    hpet().period_fs
+}
+
+/// Busy waits for `duration`, calling `start` right after the wait began.
+/// Needs no interrupts.
+pub fn wait<F>(duration: Duration, start: F) where F: FnOnce() {
+    let hpet = hpet();
+    let nanos = duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64;
+    let ticks = nanos * FEMTOS_PER_NANO / hpet.period_fs;
+    // a 32 bit counter wraps around every few minutes
+    let mask = if hpet.counter_64bit { !0 } else { 0xFFFF_FFFF };
+
+    let start_count = counter();
+    start();
+    while (counter().wrapping_sub(start_count) & mask) < ticks {}
+}
+
+/// Makes comparator 0 fire periodically at roughly `frequency` Hz on IRQ 0,
+/// instead of the PIT. Returns the real period in nanoseconds, or `None` if
+/// the comparator can't run periodically.
+pub fn start_periodic(frequency: u32) -> Option<u64> {
+    let hpet = hpet();
+    let config = hpet.read(Hpet::timer_config(0));
+    if config & TIMER_PERIODIC_CAPABLE == 0 {
+        return None;
+    }
+
+    let mut ticks = 1_000_000_000 * FEMTOS_PER_NANO / frequency as u64 / hpet.period_fs;
+    if ticks < hpet.minimum_tick {
+        ticks = hpet.minimum_tick;
+    }
+
+    // stop the counter while the comparator is set up, so the first period
+    // can't be missed
+    let general_config = hpet.read(REG_CONFIG);
+    hpet.write(REG_CONFIG, general_config & !CONFIG_ENABLE);
+
+    // the first write sets the comparator, the second one the period added
+    // on every expiry
+    hpet.write(Hpet::timer_config(0), config | TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC | TIMER_SET_ACCUMULATOR);
+    hpet.write(Hpet::timer_comparator(0), counter() + ticks);
+    hpet.write(Hpet::timer_comparator(0), ticks);
+
+    hpet.write(REG_CONFIG, general_config | CONFIG_LEGACY_REPLACEMENT | CONFIG_ENABLE);
+
+    Some(ticks * hpet.period_fs / FEMTOS_PER_NANO)
+}
+
+fn hpet() -> &'static Hpet {
+    HPET.try().expect("HPET not initialized")
+}
+
+fn map_registers(addr: PhysicalAddress) -> usize {
+    memory::identity_map(addr, PAGE_SIZE, WRITABLE | NO_CACHE | NO_EXECUTE);
+    addr
+}
@@ -0,0 +1,142 @@
+/*
+ *  Monotonic kernel clock.
+ *
+ *  The HPET, or PIT channel 0 if there is none, ticks at a fixed rate on IRQ 0
+ *  and the IRQ handler counts the ticks since boot. The TSC is calibrated
+ *  against the same clock for anything that needs a finer resolution, and the
+ *  RTC date read at boot turns it into wall-clock time.
+ */
+
+use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint};
+use core::time::Duration;
+
+use interrupts;
+
+pub use self::rtc::DateTime;
+pub use self::timer::{TimerId, add_timer, cancel_timer};
+
+mod apic_timer;
+pub mod hpet;
+mod timer;
+mod timer_wheel;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+const TIMER_IRQ: u8 = 0;
+
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+static NANOS_PER_TICK: AtomicUsize = AtomicUsize::new(0);
+static BOOT_TSC: AtomicUsize = AtomicUsize::new(0);
+// Unix time read from the RTC, and the value of `now` when it was read
+static RTC_UNIX_TIME: AtomicUsize = AtomicUsize::new(0);
+static RTC_READ_AT_NANOS: AtomicUsize = AtomicUsize::new(0);
+
+/// Calibrates the TSC, starts the tick at roughly `tick_rate` Hz and sets up
+/// the kernel timers. The ticks only start counting once interrupts are
+/// enabled. Should run after ACPI and the APIC are set up, to use the HPET,
+/// the APIC timer and the RTC century register.
+pub fn init(tick_rate: u32) {
+    let has_hpet = hpet::init();
+
+    // calibrate first, the reference clocks are polled and don't need interrupts
+    let tsc_frequency = tsc::calibrate();
+    BOOT_TSC.store(tsc::read() as usize, Ordering::Relaxed);
+
+    let hpet_period = if has_hpet { hpet::start_periodic(tick_rate) } else { None };
+    let nanos_per_tick = hpet_period.unwrap_or_else(|| {
+        let divisor = pit::start_periodic(tick_rate);
+        divisor as u64 * 1_000_000_000 / pit::PIT_FREQUENCY
+    });
+    NANOS_PER_TICK.store(nanos_per_tick as usize, Ordering::Relaxed);
+    interrupts::register_handler(TIMER_IRQ, timer_tick);
+
+    println!("time: {} tick every {} ns, TSC at {} MHz", if hpet_period.is_some() { "HPET" } else { "PIT" },
+             nanos_per_tick, tsc_frequency / 1_000_000);
+
+    rtc::init();
+    // the Unix time goes last, `wall_clock` only uses both once it's set
+    let date = rtc::read();
+    RTC_READ_AT_NANOS.store(as_nanos(now()) as usize, Ordering::Relaxed);
+    RTC_UNIX_TIME.store(date.to_unix() as usize, Ordering::Relaxed);
+    println!("time: RTC date is {} UTC", date);
+
+    timer::init();
+}
+
+/// Number of timer ticks since `init`.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed) as u64
+}
+
+/// Time since `init`, with the resolution of one tick.
+pub fn uptime() -> Duration {
+    let nanos = ticks() * NANOS_PER_TICK.load(Ordering::Relaxed) as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// Time since `init`, measured with the TSC. Zero until `init` calibrated
+/// the TSC.
+pub fn now() -> Duration {
+    match tsc::frequency() {
+        Some(_) => tsc::cycles_to_duration(tsc::read() - boot_tsc()),
+        None => Duration::new(0, 0)
+    }
+}
+
+/// Time since the Unix epoch, based on the RTC date read at boot. `None`
+/// until `init` read the RTC.
+pub fn wall_clock() -> Option<Duration> {
+    let rtc_unix_time = RTC_UNIX_TIME.load(Ordering::Relaxed) as u64;
+    if rtc_unix_time == 0 {
+        return None;
+    }
+
+    let since_rtc_read = as_nanos(now()) - RTC_READ_AT_NANOS.load(Ordering::Relaxed) as u64;
+    Some(Duration::from_secs(rtc_unix_time) + Duration::from_nanos(since_rtc_read))
+}
+
+/// Current date and time in UTC, `None` until `init` read the RTC.
+pub fn date_time() -> Option<DateTime> {
+    wall_clock().map(|time| DateTime::from_unix(time.as_secs()))
+}
+
+fn as_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+fn boot_tsc() -> u64 {
+    BOOT_TSC.load(Ordering::Relaxed) as u64
+}
+
+/// Spins until `duration` has passed. Uses the TSC once it's calibrated,
+/// otherwise the ticks, which need interrupts to be enabled.
+pub fn sleep_busy(duration: Duration) {
+    if tsc::frequency().is_some() {
+        let start = tsc::read();
+        while tsc::cycles_to_duration(tsc::read() - start) < duration {
+            spin_loop_hint();
+        }
+    } else {
+        assert!(interrupts::are_enabled(), "sleep_busy would never return with interrupts disabled");
+        let end = uptime() + duration;
+        while uptime() < end {
+            spin_loop_hint();
+        }
+    }
+}
+
+// busy waits for `ms` milliseconds on the most precise clock which works
+// without interrupts, calling `start` once the wait began
+fn calibration_wait<F>(ms: u64, start: F) where F: FnOnce() {
+    if hpet::is_available() {
+        hpet::wait(Duration::from_millis(ms), start);
+    } else {
+        pit::wait_cycles(pit::PIT_FREQUENCY * ms / 1000, start);
+    }
+}
+
+fn timer_tick(_irq: u8) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    timer::tick();
+}
@@ -0,0 +1,66 @@
+/*
+ *  Driver for the 8253/8254 Programmable Interval Timer.
+ *
+ *  Channel 0 is wired to IRQ 0 and drives the kernel tick, channel 2 can be
+ *  gated and polled through port 0x61, which makes it usable without
+ *  interrupts for calibrating other clocks.
+ */
+
+use x86::shared::io::{inb, outb};
+
+/// Input clock of all PIT channels in Hz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+// keyboard controller port B, controls the channel 2 gate and shows its output
+const PORT_B: u16 = 0x61;
+
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const SELECT_CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+const MODE_INTERRUPT_ON_TERMINAL_COUNT: u8 = 0b000 << 1;
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+const PORT_B_GATE_2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT_2: u8 = 1 << 5;
+
+/// Makes channel 0 fire periodically at roughly `frequency` Hz. Returns the
+/// divisor which was programmed, since the real rate is `PIT_FREQUENCY / divisor`.
+pub fn start_periodic(frequency: u32) -> u16 {
+    let divisor = divisor_for(PIT_FREQUENCY / frequency as u64);
+    unsafe {
+        outb(COMMAND, SELECT_CHANNEL_0 | ACCESS_LOW_HIGH | MODE_RATE_GENERATOR);
+        outb(CHANNEL_0, divisor as u8);
+        outb(CHANNEL_0, (divisor >> 8) as u8);
+    }
+    divisor
+}
+
+/// Busy waits for `count` PIT cycles on channel 2, calling `start` right
+/// after the countdown began. Needs no interrupts. `count` must fit in 16 bits.
+pub fn wait_cycles<F>(count: u64, start: F) where F: FnOnce() {
+    let divisor = divisor_for(count);
+    unsafe {
+        // enable the gate, but not the speaker
+        let port_b = inb(PORT_B);
+        outb(PORT_B, (port_b & !PORT_B_SPEAKER) | PORT_B_GATE_2);
+
+        // in mode 0 the output goes high once the count reaches zero
+        outb(COMMAND, SELECT_CHANNEL_2 | ACCESS_LOW_HIGH | MODE_INTERRUPT_ON_TERMINAL_COUNT);
+        outb(CHANNEL_2, divisor as u8);
+        outb(CHANNEL_2, (divisor >> 8) as u8);
+        start();
+
+        while inb(PORT_B) & PORT_B_OUT_2 == 0 {}
+
+        outb(PORT_B, port_b);
+    }
+}
+
+fn divisor_for(count: u64) -> u16 {
+    assert!(count > 0 && count <= 0xFFFF, "PIT count {} out of range", count);
+    count as u16
+}
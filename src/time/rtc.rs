@@ -0,0 +1,178 @@
+/*
+ *  CMOS real-time clock.
+ *
+ *  The RTC keeps the date while the machine is off, but only has a one
+ *  second resolution and may be updating its registers while we read them,
+ *  so it's only read once at boot to anchor the monotonic clock.
+ */
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86::shared::io::{inb, outb};
+
+use acpi::fadt::Fadt;
+use interrupts::without_interrupts;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+// without a century register we assume the 21st century
+const DEFAULT_CENTURY: u16 = 20;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// 0 if the FADT doesn't name a century register
+static CENTURY_REGISTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year as u64, self.month as u64, self.day as u64);
+        days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    pub fn from_unix(timestamp: u64) -> DateTime {
+        let (year, month, day) = civil_from_days(timestamp / SECONDS_PER_DAY);
+        let seconds = timestamp % SECONDS_PER_DAY;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Looks up the century register in the FADT. ACPI has to be initialized
+/// first, if it is at all.
+pub fn init() {
+    if let Some(register) = Fadt::parse().and_then(|fadt| fadt.century_register) {
+        CENTURY_REGISTER.store(register as usize, Ordering::Relaxed);
+    }
+}
+
+/// Reads the current date and time. The RTC is assumed to run on UTC.
+pub fn read() -> DateTime {
+    without_interrupts(|| {
+        // read until two reads in a row agree, so we don't get a mix of
+        // values from before and after an update
+        let mut last = read_registers();
+        loop {
+            let current = read_registers();
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+
+        let status_b = unsafe { read_register(REG_STATUS_B) };
+        decode(last, status_b)
+    })
+}
+
+// seconds, minutes, hours, day, month, year and century, as stored
+type RawDateTime = [u8; 7];
+
+fn read_registers() -> RawDateTime {
+    unsafe {
+        while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+
+        let century = match CENTURY_REGISTER.load(Ordering::Relaxed) {
+            0 => 0,
+            register => read_register(register as u8)
+        };
+        [read_register(REG_SECONDS), read_register(REG_MINUTES), read_register(REG_HOURS),
+         read_register(REG_DAY), read_register(REG_MONTH), read_register(REG_YEAR), century]
+    }
+}
+
+fn decode(raw: RawDateTime, status_b: u8) -> DateTime {
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let value = |byte: u8| if binary { byte } else { (byte >> 4) * 10 + (byte & 0x0F) };
+
+    // the PM flag is in the top bit no matter how the hour is encoded
+    let pm = raw[2] & HOUR_PM != 0;
+    let mut hour = value(raw[2] & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (hour, true) => hour + 12,
+            (hour, false) => hour
+        };
+    }
+
+    let century = if CENTURY_REGISTER.load(Ordering::Relaxed) != 0 { value(raw[6]) as u16 } else { DEFAULT_CENTURY };
+    DateTime {
+        year: century * 100 + value(raw[5]) as u16,
+        month: value(raw[4]),
+        day: value(raw[3]),
+        hour: hour,
+        minute: value(raw[1]),
+        second: value(raw[0])
+    }
+}
+
+unsafe fn read_register(register: u8) -> u8 {
+    outb(CMOS_INDEX, register);
+    inb(CMOS_DATA)
+}
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar, with
+// years starting in March so the leap day is the last day of the year
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// inverse of days_from_civil
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
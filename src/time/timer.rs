@@ -0,0 +1,100 @@
+/*
+ *  Kernel timers.
+ *
+ *  Callbacks are kept in a timer wheel counting milliseconds since boot. With
+ *  a local APIC its timer is armed for the next expiry only, otherwise the
+ *  wheel is checked on every PIT tick.
+ */
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use alloc::boxed::Box;
+use spin::{Mutex, Once};
+
+use interrupts::without_interrupts;
+use time::{self, apic_timer, tsc};
+use time::timer_wheel::TimerWheel;
+
+pub use time::timer_wheel::TimerId;
+
+static WHEEL: Once<Mutex<TimerWheel>> = Once::new();
+static APIC_TIMER: AtomicBool = AtomicBool::new(false);
+
+pub fn init() {
+    WHEEL.call_once(|| Mutex::new(TimerWheel::new(now_ms())));
+
+    if apic_timer::init(run_expired) {
+        APIC_TIMER.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Calls `callback` from interrupt context once `delay` has passed, rounded
+/// up to the next millisecond. The callback must not block.
+pub fn add_timer<F>(delay: Duration, callback: F) -> TimerId where F: FnMut() + Send + 'static {
+    let delay_ms = delay.as_secs() * 1000 + (delay.subsec_nanos() as u64 + 999_999) / 1_000_000;
+    let callback = Box::new(callback);
+
+    without_interrupts(|| {
+        let mut wheel = wheel().lock();
+        let id = wheel.insert(now_ms() + delay_ms, callback);
+        rearm(&wheel);
+        id
+    })
+}
+
+/// Cancels a timer, returns false if it already fired.
+pub fn cancel_timer(id: TimerId) -> bool {
+    without_interrupts(|| {
+        let mut wheel = wheel().lock();
+        let cancelled = wheel.cancel(id);
+        rearm(&wheel);
+        cancelled
+    })
+}
+
+/// Called on every PIT tick, does nothing if the APIC timer is in charge.
+pub fn tick() {
+    if !APIC_TIMER.load(Ordering::Relaxed) && WHEEL.try().is_some() {
+        run_expired();
+    }
+}
+
+// runs in interrupt context
+fn run_expired() {
+    let now = now_ms();
+    loop {
+        let expired = wheel().lock().pop_expired(now);
+
+        // the lock is released, so callbacks can add and cancel timers themselves
+        match expired {
+            Some(mut timer) => (timer.callback)(),
+            None => break
+        }
+    }
+
+    rearm(&wheel().lock());
+}
+
+fn rearm(wheel: &TimerWheel) {
+    if !APIC_TIMER.load(Ordering::Relaxed) {
+        return;
+    }
+
+    match wheel.next_event() {
+        Some(event) => {
+            let tsc_frequency = tsc::frequency().expect("TSC not calibrated");
+            apic_timer::set_deadline(time::boot_tsc() + event * tsc_frequency / 1000);
+        }
+        None => apic_timer::stop()
+    }
+}
+
+fn now_ms() -> u64 {
+    let now = time::now();
+    now.as_secs() * 1000 + now.subsec_nanos() as u64 / 1_000_000
+}
+
+fn wheel() -> &'static Mutex<TimerWheel> {
+    WHEEL.try().expect("Timers not initialized")
+}
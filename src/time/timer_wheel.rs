@@ -0,0 +1,202 @@
+/*
+ *  Hierarchical timer wheel.
+ *
+ *  Each level has 64 slots, a slot of level n covering 64^n time units. Timers
+ *  are put into the lowest level whose range covers their delay, and a slot
+ *  of level n is moved down a level when level n - 1 wraps around to it, so
+ *  inserting and expiring a timer never needs a sorted list. Every level
+ *  keeps a bitmap of its non-empty slots, which lets the wheel skip straight
+ *  to the next slot that has something to do.
+ *
+ *  Slots are linked lists of boxed timers, so moving timers between slots and
+ *  handing out expired ones never allocates.
+ */
+
+use core::cmp;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+const LEVELS: usize = 4;
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+
+// longest delay the wheel can hold, later timers wait in the last level and
+// are put back in as time passes
+const MAX_DELAY: u64 = (1 << (SLOT_BITS * LEVELS)) - 1;
+
+pub type Callback = Box<FnMut() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+pub struct Timer {
+    pub id: TimerId,
+    pub expires: u64,
+    pub callback: Callback,
+    // next timer in the same slot
+    next: Option<Box<Timer>>
+}
+
+pub struct TimerWheel {
+    now: u64,
+    next_id: u64,
+    // LEVELS * SLOTS slots, level by level
+    slots: Vec<Option<Box<Timer>>>,
+    // bit i of level n is set if slot i of that level holds a timer
+    occupied: [u64; LEVELS],
+    // timers of the last expired slot which haven't been handed out yet
+    expired: Option<Box<Timer>>
+}
+
+impl TimerWheel {
+    pub fn new(now: u64) -> TimerWheel {
+        TimerWheel {
+            now: now,
+            next_id: 0,
+            slots: (0..LEVELS * SLOTS).map(|_| None).collect(),
+            occupied: [0; LEVELS],
+            expired: None
+        }
+    }
+
+    /// Adds a timer which expires at `expires`. Timers in the past expire on
+    /// the next call to `pop_expired`.
+    pub fn insert(&mut self, expires: u64, callback: Callback) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        let expires = cmp::max(expires, self.now + 1);
+        self.place(Box::new(Timer { id: id, expires: expires, callback: callback, next: None }));
+        id
+    }
+
+    /// Removes a timer, returns false if it already expired or never existed.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        if remove(&mut self.expired, id) {
+            return true;
+        }
+
+        for index in 0..self.slots.len() {
+            let (level, slot) = (index / SLOTS, index % SLOTS);
+            if self.occupied[level] & 1 << slot != 0 && remove(&mut self.slots[index], id) {
+                if self.slots[index].is_none() {
+                    self.occupied[level] &= !(1 << slot);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Earliest time the wheel has something to do, either expire a timer or
+    /// move a slot down a level. Never later than the earliest expiry.
+    pub fn next_event(&self) -> Option<u64> {
+        if self.expired.is_some() {
+            return Some(self.now);
+        }
+
+        (0..LEVELS).filter_map(|level| {
+            // the slot which is up next comes first after rotating
+            let current = self.now >> (SLOT_BITS * level);
+            let pending = self.occupied[level].rotate_right(((current + 1) as usize & (SLOTS - 1)) as u32);
+            if pending == 0 {
+                None
+            } else {
+                Some((current + 1 + pending.trailing_zeros() as u64) << (SLOT_BITS * level))
+            }
+        }).min()
+    }
+
+    /// Moves the wheel forward towards `now` and returns the next timer which
+    /// expired on the way, in the order they expired. Returns `None` once the
+    /// wheel reached `now` and every expired timer was handed out.
+    pub fn pop_expired(&mut self, now: u64) -> Option<Box<Timer>> {
+        loop {
+            if let Some(mut timer) = self.expired.take() {
+                self.expired = timer.next.take();
+                return Some(timer);
+            }
+
+            match self.next_event() {
+                Some(event) if event <= now => {
+                    // nothing happens before the next event, so skip right to it
+                    self.now = event - 1;
+                    self.step();
+                }
+                _ => {
+                    self.now = cmp::max(self.now, now);
+                    return None;
+                }
+            }
+        }
+    }
+
+    // moves forward by one unit, first cascading the slot of every level
+    // which the levels below just wrapped around to, then expiring the
+    // current slot
+    fn step(&mut self) {
+        self.now += 1;
+
+        for level in (1..LEVELS).rev() {
+            if self.now & ((1 << (SLOT_BITS * level)) - 1) == 0 {
+                let mut timers = self.take_slot(level, self.now);
+                while let Some(mut timer) = timers {
+                    timers = timer.next.take();
+                    self.place(timer);
+                }
+            }
+        }
+
+        let mut timers = self.take_slot(0, self.now);
+        while let Some(mut timer) = timers {
+            timers = timer.next.take();
+            if timer.expires <= self.now {
+                timer.next = self.expired.take();
+                self.expired = Some(timer);
+            } else {
+                self.place(timer);
+            }
+        }
+    }
+
+    fn take_slot(&mut self, level: usize, time: u64) -> Option<Box<Timer>> {
+        let index = slot_index(level, time);
+        self.occupied[level] &= !(1 << (index % SLOTS));
+        self.slots[index].take()
+    }
+
+    fn place(&mut self, mut timer: Box<Timer>) {
+        let delay = cmp::min(timer.expires.saturating_sub(self.now), MAX_DELAY);
+
+        let mut level = 0;
+        while level + 1 < LEVELS && delay >= 1 << (SLOT_BITS * (level + 1)) {
+            level += 1;
+        }
+
+        let index = slot_index(level, self.now + delay);
+        timer.next = self.slots[index].take();
+        self.slots[index] = Some(timer);
+        self.occupied[level] |= 1 << (index % SLOTS);
+    }
+}
+
+fn slot_index(level: usize, time: u64) -> usize {
+    level * SLOTS + ((time >> (SLOT_BITS * level)) as usize & (SLOTS - 1))
+}
+
+// unlinks the timer `id` from a slot's list, the order of the others changes
+fn remove(list: &mut Option<Box<Timer>>, id: TimerId) -> bool {
+    let mut found = false;
+    let mut timers = list.take();
+    while let Some(mut timer) = timers {
+        timers = timer.next.take();
+        if timer.id == id {
+            found = true;
+        } else {
+            timer.next = list.take();
+            *list = Some(timer);
+        }
+    }
+    found
+}
@@ -0,0 +1,60 @@
+/*
+ *  Time Stamp Counter.
+ *
+ *  The TSC counts at a fixed rate on any CPU with an invariant TSC, which we
+ *  measure once against the HPET or PIT to turn cycle counts into real time.
+ */
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use time;
+
+// length of the calibration run, short enough for the 16 bit PIT counter
+const CALIBRATION_MS: u64 = 50;
+
+static FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+
+/// Current value of the TSC.
+pub fn read() -> u64 {
+    unsafe { ::core::arch::x86_64::_rdtsc() as u64 }
+}
+
+/// TSC frequency in Hz, or `None` if it hasn't been calibrated.
+pub fn frequency() -> Option<u64> {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency as u64)
+    }
+}
+
+/// Measures the TSC frequency against the HPET, or PIT channel 2 if there is
+/// none, and returns it.
+pub fn calibrate() -> u64 {
+    if !is_invariant() {
+        println!("tsc: not invariant, timestamps may drift with the CPU frequency");
+    }
+
+    let mut start = 0;
+    time::calibration_wait(CALIBRATION_MS, || start = read());
+    let cycles = read() - start;
+
+    let frequency = cycles * 1000 / CALIBRATION_MS;
+    FREQUENCY.store(frequency as usize, Ordering::Relaxed);
+    frequency
+}
+
+/// Converts a difference of two TSC values into a `Duration`.
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    let frequency = frequency().expect("TSC not calibrated");
+    let secs = cycles / frequency;
+    let nanos = (cycles % frequency) * 1_000_000_000 / frequency;
+    Duration::new(secs, nanos as u32)
+}
+
+// checks the invariant TSC bit of the advanced power management info
+fn is_invariant() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    unsafe { __cpuid(0x8000_0007).edx & (1 << 8) != 0 }
+}
@@ -1,42 +1,63 @@
 /*
  *  Abstraction over the unsafe usage of the VGA text buffer memory.
  *
- *  This will allow us to print arbitrary text to the console,
- *  as well as support the standard println! and format! macros.
+ *  This will allow us to print arbitrary text to the screen, the print!
+ *  and println! macros reach it through the console.
  */
 
 #![allow(dead_code)]
 
 use spin::Mutex;
+use x86::shared::io::{inb, outb};
 
-const COLOR_CODE: ColorCode = ColorCode::new(Color::LightGreen, Color::Black);
-const RAW_WRITER: Writer = Writer::new(0, COLOR_CODE, 0xB8000);
+use ansi::{self, Attributes, Terminal};
+use cp437;
+use interrupts::without_interrupts;
+
+pub const DEFAULT_FOREGROUND: Color = Color::LightGreen;
+pub const DEFAULT_BACKGROUND: Color = Color::Black;
+const RAW_WRITER: Writer = Writer::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND, 0xB8000);
 pub static WRITER: Mutex<Writer> = Mutex::new(RAW_WRITER);
 
-macro_rules! print {
-    ($($arg:tt)*) => ({
-        $crate::vga_buffer::print(format_args!($($arg)*));
-    });
+// CRT controller registers, selected through the index port
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0F;
+
+// underline cursor in the bottom two scanlines of the 16 scanline cells
+const CURSOR_START_SCANLINE: u8 = 14;
+const CURSOR_END_SCANLINE: u8 = 15;
+
+// rows kept after they scroll off the top of the screen
+const SCROLLBACK_LINES: usize = 500;
+
+pub fn clear_screen() {
+    without_interrupts(|| WRITER.lock().clear());
 }
 
-macro_rules! println {
-    ($fmt:expr) => (print!(concat!($fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+/// Scrolls the view `lines` further back into the history.
+pub fn scroll_up(lines: usize) {
+    without_interrupts(|| WRITER.lock().scroll_up(lines));
 }
 
-pub fn print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+/// Scrolls the view `lines` towards the live screen.
+pub fn scroll_down(lines: usize) {
+    without_interrupts(|| WRITER.lock().scroll_down(lines));
 }
 
-pub fn clear_screen() {
-    for _ in 0..BUFFER_HEIGHT {
-        println!("");
-    }
+pub fn page_up() {
+    scroll_up(BUFFER_HEIGHT - 1);
+}
+
+pub fn page_down() {
+    scroll_down(BUFFER_HEIGHT - 1);
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Color {
     Black      = 0x0,
     Blue       = 0x1,
@@ -56,6 +77,26 @@ pub enum Color {
     WHITE      = 0xF
 }
 
+impl Color {
+    // maps the 8 ANSI colors (black, red, green, yellow, blue, magenta, cyan,
+    // white) to VGA colors, which order red and blue the other way around
+    pub fn from_ansi(index: u16, bright: bool) -> Color {
+        const NORMAL: [Color; 8] = [Color::Black, Color::Red, Color::Green, Color::Brown,
+                                    Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray];
+        const BRIGHT: [Color; 8] = [Color::DarkGray, Color::LightRed, Color::LightGreen, Color::YELLOW,
+                                    Color::LightBlue, Color::PINK, Color::LightCyan, Color::WHITE];
+        if bright { BRIGHT[index as usize % 8] } else { NORMAL[index as usize % 8] }
+    }
+
+    // the bright variant of one of the first 8 colors
+    pub fn brighten(self) -> Color {
+        const BRIGHT: [Color; 8] = [Color::DarkGray, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+                                    Color::LightRed, Color::PINK, Color::YELLOW, Color::WHITE];
+        let index = self as usize;
+        if index < 8 { BRIGHT[index] } else { self }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct ColorCode(u8);
 
@@ -81,47 +122,238 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT]
 }
 
+type Row = [ScreenChar; BUFFER_WIDTH];
+
+const BLANK_ROW: Row = [ScreenChar {
+    ascii_char: b' ',
+    color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND)
+}; BUFFER_WIDTH];
+
+/// Ring buffer of the rows which scrolled off the screen, oldest first.
+struct Scrollback {
+    rows: [Row; SCROLLBACK_LINES],
+    start: usize,
+    len: usize
+}
+
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback { rows: [BLANK_ROW; SCROLLBACK_LINES], start: 0, len: 0 }
+    }
+
+    fn push(&mut self, row: Row) {
+        let end = (self.start + self.len) % SCROLLBACK_LINES;
+        self.rows[end] = row;
+        if self.len == SCROLLBACK_LINES {
+            self.start = (self.start + 1) % SCROLLBACK_LINES;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn get(&self, index: usize) -> &Row {
+        &self.rows[(self.start + index) % SCROLLBACK_LINES]
+    }
+}
+
+use core::cmp::min;
 use core::ptr::Unique;
 
 pub struct Writer {
+    row_position: usize,
     column_position: usize,
+    saved_position: (usize, usize),
+    attributes: Attributes,
     color_code: ColorCode,
+    parser: ansi::Parser,
+    scrollback: Scrollback,
+    // number of rows the view is scrolled back, 0 shows the live screen
+    view_offset: usize,
+    // the live screen, saved while the view shows the history
+    live_screen: [Row; BUFFER_HEIGHT],
     buffer: Unique<Buffer>
 }
 
 impl Writer {
-    const fn new(column_position: usize, color_code: ColorCode, buffer: usize) -> Writer {
+    const fn new(foreground: Color, background: Color, buffer: usize) -> Writer {
         Writer {
-            column_position: column_position,
-            color_code: color_code,
+            row_position: 0,
+            column_position: 0,
+            saved_position: (0, 0),
+            attributes: Attributes::new(foreground, background),
+            color_code: ColorCode::new(foreground, background),
+            parser: ansi::Parser::new(),
+            scrollback: Scrollback::new(),
+            view_offset: 0,
+            live_screen: [BLANK_ROW; BUFFER_HEIGHT],
             buffer: unsafe { Unique::new_unchecked(buffer as *mut _) }
         }
     }
 
+    /// Writes a string, interpreting ANSI escape sequences in it. Characters
+    /// outside of ASCII are shown as their code page 437 glyph, or as a
+    /// placeholder if there is none.
     pub fn write_str(&mut self, s: &str) {
-        for byte in s.bytes() {
-            self.write_byte(byte)
+        self.scroll_to_live();
+        for c in s.chars() {
+            self.process_char(c);
         }
+        self.update_cursor();
     }
 
+    /// Writes a raw code page 437 byte, which may be part of an escape sequence.
     pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.write_new_line(),
-            _     => self.write_character(byte)
+        self.scroll_to_live();
+        self.process_char(if byte < 0x80 { byte as char } else { cp437::to_char(byte) });
+        self.update_cursor();
+    }
+
+    /// Moves the cursor, the next character is written at `(row, col)`.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        assert!(row < BUFFER_HEIGHT && col < BUFFER_WIDTH, "Position ({}, {}) outside of the screen", row, col);
+        self.row_position = row;
+        self.column_position = col;
+        self.update_cursor();
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    /// Shows older rows from the scrollback history. The live screen comes
+    /// back with `scroll_down`, or as soon as anything is written.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let offset = min(self.view_offset + lines, self.scrollback.len);
+        self.set_view_offset(offset);
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        let offset = self.view_offset.saturating_sub(lines);
+        self.set_view_offset(offset);
+    }
+
+    pub fn scroll_to_live(&mut self) {
+        self.set_view_offset(0);
+    }
+
+    fn set_view_offset(&mut self, offset: usize) {
+        if offset == self.view_offset {
+            return;
+        }
+
+        if self.view_offset == 0 {
+            // save the screen in place, a copy of it would take up half the boot stack
+            let &mut Writer { ref mut buffer, ref mut live_screen, .. } = self;
+            let buf = unsafe { buffer.as_mut() };
+            for (row, saved) in live_screen.iter_mut().enumerate() {
+                for (col, chr) in saved.iter_mut().enumerate() {
+                    *chr = buf.chars[row][col].read();
+                }
+            }
         }
+        self.view_offset = offset;
+
+        // the view is a window into the history followed by the live screen
+        let history_len = self.scrollback.len;
+        for row in 0..BUFFER_HEIGHT {
+            let index = history_len - offset + row;
+            let source = if index < history_len { *self.scrollback.get(index) } else { self.live_screen[index - history_len] };
+            let buf = self.buffer();
+            for col in 0..BUFFER_WIDTH {
+                buf.chars[row][col].write(source[col]);
+            }
+        }
+        self.update_cursor();
     }
 
-    fn write_character(&mut self, chr: u8) {
+    /// Blanks the whole screen and moves the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        self.scroll_to_live();
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+
+        enable_cursor();
+        self.set_position(0, 0);
+    }
+
+    // moves the blinking hardware cursor to the current position, or off the
+    // screen while the history is shown
+    fn update_cursor(&self) {
+        let col = if self.column_position < BUFFER_WIDTH { self.column_position } else { BUFFER_WIDTH - 1 };
+        let position = if self.view_offset > 0 {
+            (BUFFER_HEIGHT * BUFFER_WIDTH) as u16
+        } else {
+            (self.row_position * BUFFER_WIDTH + col) as u16
+        };
+        unsafe {
+            outb(CRTC_INDEX, CRTC_CURSOR_LOCATION_LOW);
+            outb(CRTC_DATA, position as u8);
+            outb(CRTC_INDEX, CRTC_CURSOR_LOCATION_HIGH);
+            outb(CRTC_DATA, (position >> 8) as u8);
+        }
+    }
+
+    fn buffer(&mut self) -> &mut Buffer {
+        unsafe {
+            self.buffer.as_mut()
+        }
+    }
+
+
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
+            ascii_char: b' ',
+            color_code: self.color_code
+        }
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        self.clear_columns(row, 0, BUFFER_WIDTH);
+    }
+}
+
+impl Terminal for Writer {
+    fn dimensions(&self) -> (usize, usize) {
+        (BUFFER_HEIGHT, BUFFER_WIDTH)
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        self.position()
+    }
+
+    fn move_cursor(&mut self, row: usize, column: usize) {
+        self.row_position = row;
+        self.column_position = column;
+    }
+
+    fn saved_cursor(&mut self) -> &mut (usize, usize) {
+        &mut self.saved_position
+    }
+
+    fn parser(&mut self) -> &mut ansi::Parser {
+        &mut self.parser
+    }
+
+    fn attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+
+    fn attributes_changed(&mut self) {
+        self.color_code = ColorCode::new(self.attributes.text_color(), self.attributes.background);
+    }
+
+    fn write_character(&mut self, c: char) {
         if self.column_position >= BUFFER_WIDTH {
             self.write_new_line();
         }
 
-        let row = BUFFER_HEIGHT - 1;
+        let row = self.row_position;
         let col = self.column_position;
 
         let color_code = self.color_code;
         self.buffer().chars[row][col].write(ScreenChar {
-            ascii_char: chr,
+            ascii_char: cp437::from_char_or_placeholder(c),
             color_code: color_code
         });
         self.column_position += 1;
@@ -129,6 +361,18 @@ impl Writer {
 
 
     fn write_new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position + 1 < BUFFER_HEIGHT {
+            self.row_position += 1;
+            return;
+        }
+
+        let mut top_row = BLANK_ROW;
+        for col in 0..BUFFER_WIDTH {
+            top_row[col] = self.buffer().chars[0][col].read();
+        }
+        self.scrollback.push(top_row);
+
         for row in 1..BUFFER_HEIGHT {
             let buf = self.buffer();
             for col in 0..BUFFER_WIDTH {
@@ -138,24 +382,14 @@ impl Writer {
         }
 
         self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
     }
 
-    fn buffer(&mut self) -> &mut Buffer {
-        unsafe {
-            self.buffer.as_mut()
-        }
-    }
-
-
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
-            ascii_char: b' ',
-            color_code: self.color_code
-        };
+    // blanks the columns [start, end) of a row
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank();
 
         let buf = self.buffer();
-        for col in 0..BUFFER_WIDTH {
+        for col in start..end {
             buf.chars[row][col].write(blank)
         }
     }
@@ -169,3 +403,16 @@ impl fmt::Write for Writer {
         Ok(())
     }
 }
+
+// sets the cursor shape, which also turns it on if the BIOS disabled it
+fn enable_cursor() {
+    unsafe {
+        outb(CRTC_INDEX, CRTC_CURSOR_START);
+        let start = inb(CRTC_DATA) & 0xC0;
+        outb(CRTC_DATA, start | CURSOR_START_SCANLINE);
+
+        outb(CRTC_INDEX, CRTC_CURSOR_END);
+        let end = inb(CRTC_DATA) & 0xE0;
+        outb(CRTC_DATA, end | CURSOR_END_SCANLINE);
+    }
+}
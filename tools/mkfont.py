@@ -0,0 +1,353 @@
+#!/usr/bin/env python3
+#
+#  Generates src/font/default8x16.psf, the built-in console font.
+#
+#  Letters, digits and symbols are rasterized from the outlines of DejaVu Sans
+#  Mono Bold (DejaVu fonts 2.37, DejaVuSansMono-Bold.ttf), the box drawing and
+#  block characters are drawn directly for the 8x16 cell. The glyphs are
+#  written as a PSF1 font without a unicode table, in code page 437 order.
+#
+#  usage: mkfont.py [path/to/DejaVuSansMono-Bold.ttf] [output.psf]
+#
+#  Only the standard library is needed. Running it with the defaults
+#  reproduces the checked in font byte for byte.
+
+import struct
+import sys
+
+DEFAULT_TTF = '/usr/share/fonts/truetype/dejavu/DejaVuSansMono-Bold.ttf'
+DEFAULT_OUTPUT = 'src/font/default8x16.psf'
+
+WIDTH, HEIGHT = 8, 16
+# horizontal advance of every DejaVu Sans Mono glyph, scaled to the cell width
+ADVANCE = 1233
+SCALE = WIDTH / ADVANCE
+# pixel row of the baseline, counted from the top of the cell
+BASELINE = 12.0
+# subsamples per pixel in each direction, and the share of them which has to
+# be inside the outline for the pixel to be set
+SUBSAMPLES = 6
+THRESHOLD = 0.5
+# points per quadratic curve segment when flattening outlines
+CURVE_STEPS = 8
+
+PSF1_MAGIC = b'\x36\x04'
+
+# unicode characters of the code page 437 glyphs 0x00 to 0x1F and 0x80 to 0xFF
+CP437_LOW = '\0☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼'
+CP437_HIGH = ('ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»'
+              '░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀'
+              'αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\xa0')
+
+# glyphs which are left blank: NUL, space and the non-breaking space
+BLANK = (0x00, 0x20, 0xFF)
+
+# box drawing characters as the line style going up, down, left and right
+# from the center of the cell, 0 for none, 1 for single and 2 for double
+BOX = {
+    0xB3: (1, 1, 0, 0), 0xB4: (1, 1, 1, 0), 0xB5: (1, 1, 2, 0), 0xB6: (2, 2, 1, 0),
+    0xB7: (0, 2, 1, 0), 0xB8: (0, 1, 2, 0), 0xB9: (2, 2, 2, 0), 0xBA: (2, 2, 0, 0),
+    0xBB: (0, 2, 2, 0), 0xBC: (2, 0, 2, 0), 0xBD: (2, 0, 1, 0), 0xBE: (1, 0, 2, 0),
+    0xBF: (0, 1, 1, 0), 0xC0: (1, 0, 0, 1), 0xC1: (1, 0, 1, 1), 0xC2: (0, 1, 1, 1),
+    0xC3: (1, 1, 0, 1), 0xC4: (0, 0, 1, 1), 0xC5: (1, 1, 1, 1), 0xC6: (1, 1, 0, 2),
+    0xC7: (2, 2, 0, 1), 0xC8: (2, 0, 0, 2), 0xC9: (0, 2, 0, 2), 0xCA: (2, 0, 2, 2),
+    0xCB: (0, 2, 2, 2), 0xCC: (2, 2, 0, 2), 0xCD: (0, 0, 2, 2), 0xCE: (2, 2, 2, 2),
+    0xCF: (1, 0, 2, 2), 0xD0: (2, 0, 1, 1), 0xD1: (0, 1, 2, 2), 0xD2: (0, 2, 1, 1),
+    0xD3: (2, 0, 0, 1), 0xD4: (1, 0, 0, 2), 0xD5: (0, 1, 0, 2), 0xD6: (0, 2, 0, 1),
+    0xD7: (2, 2, 1, 1), 0xD8: (1, 1, 2, 2), 0xD9: (1, 0, 1, 0), 0xDA: (0, 1, 0, 1),
+}
+
+# shades and blocks, one byte per pixel row
+BLOCKS = {
+    0xB0: [0x22 if row % 2 == 0 else 0x88 for row in range(HEIGHT)],
+    0xB1: [0x55 if row % 2 == 0 else 0xAA for row in range(HEIGHT)],
+    0xB2: [0xDD if row % 2 == 0 else 0x77 for row in range(HEIGHT)],
+    0xDB: [0xFF] * 16,
+    0xDC: [0x00] * 8 + [0xFF] * 8,
+    0xDD: [0xF0] * 16,
+    0xDE: [0x0F] * 16,
+    0xDF: [0xFF] * 8 + [0x00] * 8,
+}
+
+
+def cp437_char(byte):
+    if byte < 0x20:
+        return CP437_LOW[byte]
+    if byte < 0x7F:
+        return chr(byte)
+    if byte == 0x7F:
+        return '⌂'
+    return CP437_HIGH[byte - 0x80]
+
+
+class TrueType:
+    """The parts of a TrueType font needed to get at glyph outlines."""
+
+    def __init__(self, data):
+        self.data = data
+        self.tables = {}
+        num_tables = struct.unpack('>H', data[4:6])[0]
+        for i in range(num_tables):
+            tag, _, offset, length = struct.unpack('>4sIII', data[12 + 16 * i:28 + 16 * i])
+            self.tables[tag.decode()] = (offset, length)
+
+        head = self.table('head')
+        loca_format = struct.unpack('>h', head[50:52])[0]
+        glyph_count = struct.unpack('>H', self.table('maxp')[4:6])[0]
+        loca = self.table('loca')
+        if loca_format == 0:
+            self.offsets = [struct.unpack('>H', loca[2 * i:2 * i + 2])[0] * 2 for i in range(glyph_count + 1)]
+        else:
+            self.offsets = [struct.unpack('>I', loca[4 * i:4 * i + 4])[0] for i in range(glyph_count + 1)]
+        self.glyf = self.table('glyf')
+        self.read_cmap()
+
+    def table(self, tag):
+        offset, length = self.tables[tag]
+        return self.data[offset:offset + length]
+
+    def read_cmap(self):
+        # the format 4 subtable of the Windows platform maps the BMP
+        cmap = self.table('cmap')
+        count = struct.unpack('>H', cmap[2:4])[0]
+        subtable = None
+        for i in range(count):
+            platform, _, offset = struct.unpack('>HHI', cmap[4 + 8 * i:12 + 8 * i])
+            if struct.unpack('>H', cmap[offset:offset + 2])[0] == 4 and platform == 3:
+                subtable = offset
+
+        c = cmap[subtable:]
+        seg_x2 = struct.unpack('>H', c[6:8])[0]
+        segments = seg_x2 // 2
+        self.cmap = c
+        self.ends = struct.unpack('>%dH' % segments, c[14:14 + seg_x2])
+        self.starts = struct.unpack('>%dH' % segments, c[16 + seg_x2:16 + 2 * seg_x2])
+        self.deltas = struct.unpack('>%dh' % segments, c[16 + 2 * seg_x2:16 + 3 * seg_x2])
+        self.range_offsets_pos = 16 + 3 * seg_x2
+        self.range_offsets = struct.unpack('>%dH' % segments,
+                                           c[self.range_offsets_pos:self.range_offsets_pos + seg_x2])
+
+    def glyph_id(self, code_point):
+        for i, (start, end) in enumerate(zip(self.starts, self.ends)):
+            if start <= code_point <= end:
+                if self.range_offsets[i] == 0:
+                    return (code_point + self.deltas[i]) & 0xFFFF
+                pos = self.range_offsets_pos + 2 * i + self.range_offsets[i] + 2 * (code_point - start)
+                glyph = struct.unpack('>H', self.cmap[pos:pos + 2])[0]
+                return (glyph + self.deltas[i]) & 0xFFFF if glyph else 0
+        return 0
+
+    def contours(self, glyph, dx=0, dy=0):
+        """Contours of a glyph as lists of (x, y, on_curve) points."""
+        start, end = self.offsets[glyph], self.offsets[glyph + 1]
+        if start == end:
+            return []
+
+        data = self.glyf[start:end]
+        contour_count = struct.unpack('>h', data[0:2])[0]
+        if contour_count < 0:
+            return self.composite_contours(data, dx, dy)
+
+        end_points = struct.unpack('>%dH' % contour_count, data[10:10 + 2 * contour_count])
+        pos = 10 + 2 * contour_count
+        instructions_length = struct.unpack('>H', data[pos:pos + 2])[0]
+        pos += 2 + instructions_length
+
+        point_count = end_points[-1] + 1
+        flags = []
+        while len(flags) < point_count:
+            flag = data[pos]
+            pos += 1
+            flags.append(flag)
+            if flag & 8:
+                repeat = data[pos]
+                pos += 1
+                flags += [flag] * repeat
+
+        xs, pos = self.coordinates(data, pos, flags, 2, 16)
+        ys, pos = self.coordinates(data, pos, flags, 4, 32)
+
+        contours = []
+        first = 0
+        for last in end_points:
+            contours.append([(xs[i] + dx, ys[i] + dy, flags[i] & 1) for i in range(first, last + 1)])
+            first = last + 1
+        return contours
+
+    def coordinates(self, data, pos, flags, short_flag, same_flag):
+        values = []
+        value = 0
+        for flag in flags:
+            if flag & short_flag:
+                delta = data[pos]
+                pos += 1
+                value += delta if flag & same_flag else -delta
+            elif not flag & same_flag:
+                value += struct.unpack('>h', data[pos:pos + 2])[0]
+                pos += 2
+            values.append(value)
+        return values, pos
+
+    def composite_contours(self, data, dx, dy):
+        contours = []
+        pos = 10
+        while True:
+            flags, glyph = struct.unpack('>HH', data[pos:pos + 4])
+            pos += 4
+            if flags & 0x01:
+                arg1, arg2 = struct.unpack('>hh', data[pos:pos + 4])
+                pos += 4
+            else:
+                arg1, arg2 = struct.unpack('>bb', data[pos:pos + 2])
+                pos += 2
+
+            # scaling isn't used by any of the glyphs, it's only skipped
+            if flags & 0x08:
+                pos += 2
+            elif flags & 0x40:
+                pos += 4
+            elif flags & 0x80:
+                pos += 8
+
+            contours += self.contours(glyph, dx + arg1, dy + arg2)
+            if not flags & 0x20:
+                return contours
+
+
+def flatten(points):
+    """Turns a contour with quadratic curves into a polygon."""
+    # consecutive off curve points have an implied on curve point between them
+    expanded = []
+    for i, a in enumerate(points):
+        b = points[(i + 1) % len(points)]
+        expanded.append(a)
+        if not a[2] and not b[2]:
+            expanded.append(((a[0] + b[0]) / 2, (a[1] + b[1]) / 2, 1))
+
+    first_on = next(i for i, point in enumerate(expanded) if point[2])
+    expanded = expanded[first_on:] + expanded[:first_on]
+
+    polygon = []
+    i = 0
+    n = len(expanded)
+    while i < n:
+        a = expanded[i]
+        b = expanded[(i + 1) % n]
+        if b[2]:
+            polygon.append((a[0], a[1]))
+            i += 1
+        else:
+            c = expanded[(i + 2) % n]
+            for step in range(CURVE_STEPS):
+                t = step / CURVE_STEPS
+                x = (1 - t) ** 2 * a[0] + 2 * (1 - t) * t * b[0] + t * t * c[0]
+                y = (1 - t) ** 2 * a[1] + 2 * (1 - t) * t * b[1] + t * t * c[1]
+                polygon.append((x, y))
+            i += 2
+    return polygon
+
+
+def rasterize(font, code_point):
+    """Rows of an outline glyph, or None if the font has no glyph for it."""
+    glyph = font.glyph_id(code_point)
+    if glyph == 0:
+        return None
+
+    polygons = [flatten(contour) for contour in font.contours(glyph) if contour]
+    rows = []
+    for py in range(HEIGHT):
+        row = 0
+        for px in range(WIDTH):
+            coverage = 0
+            for sy in range(SUBSAMPLES):
+                y = (BASELINE - (py + (sy + .5) / SUBSAMPLES)) / SCALE
+
+                # edges crossing this scanline with their winding direction
+                crossings = []
+                for polygon in polygons:
+                    for j, (x0, y0) in enumerate(polygon):
+                        x1, y1 = polygon[(j + 1) % len(polygon)]
+                        if (y0 <= y < y1) or (y1 <= y < y0):
+                            crossings.append((x0 + (y - y0) * (x1 - x0) / (y1 - y0), 1 if y1 > y0 else -1))
+
+                for sx in range(SUBSAMPLES):
+                    x = (px + (sx + .5) / SUBSAMPLES) / SCALE
+                    if sum(direction for cross, direction in crossings if cross < x) != 0:
+                        coverage += 1
+
+            if coverage / (SUBSAMPLES * SUBSAMPLES) >= THRESHOLD:
+                row |= 0x80 >> px
+        rows.append(row)
+    return rows
+
+
+def box(up, down, left, right):
+    """Rows of a box drawing glyph. Single lines are two pixels wide, double
+    lines are two single lines with a gap of one pixel."""
+    pixels = set()
+
+    def horizontal(row, first, last):
+        for col in range(first, last + 1):
+            pixels.add((row, col))
+
+    def vertical(cols, first, last):
+        for row in range(first, last + 1):
+            for col in cols:
+                pixels.add((row, col))
+
+    single, double_left, double_right = (3, 4), (2, 3), (5, 6)
+    double_horizontal = left == 2 or right == 2
+    double_vertical = up == 2 or down == 2
+
+    if up == 1:
+        vertical(single, 0, 5 if (left == 2 and right == 2 and not down) else 7)
+    if down == 1:
+        vertical(single, 5 if (double_horizontal and not (left == 2 and right == 2 and not up)) else 7, 15)
+    if up == 2:
+        vertical(double_left, 0, 5 if left == 2 else 7)
+        vertical(double_right, 0, 5 if right == 2 else 7)
+    if down == 2:
+        vertical(double_left, 7 if left == 2 else (5 if right == 2 else 7), 15)
+        vertical(double_right, 7 if right == 2 else (5 if left == 2 else 7), 15)
+    if left == 1:
+        horizontal(7, 0, 2 if (up == 2 and down == 2 and not right) else (6 if double_vertical else 4))
+    if right == 1:
+        horizontal(7, 5 if (up == 2 and down == 2 and not left) else (2 if double_vertical else 3), 7)
+    if left == 2:
+        horizontal(5, 0, 3 if up == 2 else (6 if down == 2 else 4))
+        horizontal(7, 0, 3 if down == 2 else (6 if up == 2 else 4))
+    if right == 2:
+        horizontal(5, 5 if up == 2 else (2 if down == 2 else 3), 7)
+        horizontal(7, 5 if down == 2 else (2 if up == 2 else 3), 7)
+
+    return [sum(0x80 >> col for col in range(WIDTH) if (row, col) in pixels) for row in range(HEIGHT)]
+
+
+def main():
+    ttf = sys.argv[1] if len(sys.argv) > 1 else DEFAULT_TTF
+    output = sys.argv[2] if len(sys.argv) > 2 else DEFAULT_OUTPUT
+
+    with open(ttf, 'rb') as f:
+        font = TrueType(f.read())
+
+    psf = bytearray(PSF1_MAGIC + bytes([0, HEIGHT]))
+    for byte in range(256):
+        if byte in BOX:
+            rows = box(*BOX[byte])
+        elif byte in BLOCKS:
+            rows = BLOCKS[byte]
+        elif byte in BLANK:
+            rows = [0] * HEIGHT
+        else:
+            rows = rasterize(font, ord(cp437_char(byte)))
+            if rows is None:
+                print('no glyph for 0x%02X %s' % (byte, cp437_char(byte)), file=sys.stderr)
+                rows = [0] * HEIGHT
+        psf += bytes(rows)
+
+    with open(output, 'wb') as f:
+        f.write(psf)
+
+
+if __name__ == '__main__':
+    main()